@@ -0,0 +1,44 @@
+use std::process::Command;
+
+/// Names the build after the commit it's built from, so the lobby can tell builds apart even
+/// when the version number is the same
+fn main() {
+    let version = env!("CARGO_PKG_VERSION");
+    // a build from a source archive has no commit to go by, only its version
+    let build_id = match git(&["rev-parse", "--short=12", "HEAD"]) {
+        Some(commit) => {
+            // uncommitted changes make a build of their own, they may not play the same
+            let dirty = git(&["status", "--porcelain"]).is_some_and(|status| !status.is_empty());
+            let dirty = if dirty { "-dirty" } else { "" };
+            format!("{version}+{commit}{dirty}")
+        }
+        None => version.to_string(),
+    };
+    println!("cargo:rustc-env=BUILD_ID={build_id}");
+
+    // worktrees keep their HEAD and index apart from the refs they share with the main checkout
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/index");
+    }
+    if let Some(common_dir) = git(&["rev-parse", "--git-common-dir"]) {
+        println!("cargo:rerun-if-changed={common_dir}/refs");
+        println!("cargo:rerun-if-changed={common_dir}/packed-refs");
+    }
+    // edits that aren't staged yet only show up in the files themselves
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=assets");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Output of a git command, nothing if git isn't there or this isn't a checkout
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|output| output.trim().to_string())
+}
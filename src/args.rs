@@ -1,7 +1,14 @@
+use crate::{
+    settings::{
+        DESYNC_INTERVAL_RANGE, DISCONNECT_NOTIFY_RANGE, DISCONNECT_TIMEOUT_RANGE, FPS_RANGE,
+        INPUT_DELAY_RANGE, MAX_PREDICTION_RANGE, SPECTATOR_DELAY_RANGE,
+    },
+    PLAYERS,
+};
 use bevy::prelude::*;
-use clap::Parser;
+use clap::{builder::RangedU64ValueParser, Parser};
 use serde::Deserialize;
-use std::ffi::OsString;
+use std::{ffi::OsString, net::SocketAddr, ops::RangeInclusive};
 
 #[derive(Parser, Debug, Clone, Deserialize, Resource)]
 #[serde(default)]
@@ -17,8 +24,66 @@ pub struct Args {
     #[clap(long)]
     pub room: Option<String>,
 
-    #[clap(long, short, default_value = "2")]
+    /// Players in an online match, only two for now
+    #[clap(long, short, default_value = "2", value_parser = in_range(PLAYERS..=PLAYERS))]
     pub players: usize,
+
+    /// Address of the other player for direct connect, no matchbox server needed
+    #[clap(long)]
+    pub connect: Option<SocketAddr>,
+
+    /// UDP port direct connect listens on, the other player connects to it.
+    /// Hosting a LAN game runs its signaling server on the same port, over TCP.
+    #[clap(long, default_value = "7000")]
+    pub local_port: u16,
+
+    /// Nickname shown to the other players in the lobby
+    #[clap(long)]
+    pub name: Option<String>,
+
+    /// Name of the stage to pick, online it's only where the lobby's pick starts
+    #[clap(long)]
+    pub stage: Option<String>,
+
+    /// Frames local inputs are held back, trading responsiveness for fewer rollbacks
+    #[clap(long, env, default_value = "0", value_parser = in_range(INPUT_DELAY_RANGE))]
+    pub input_delay: usize,
+
+    /// Frames the session can run ahead of the remote inputs before waiting for them
+    #[clap(long, env, default_value = "12", value_parser = in_range(MAX_PREDICTION_RANGE))]
+    pub max_prediction: usize,
+
+    /// Frames simulated per second, online the host's pick is used
+    #[clap(long, env, default_value = "60", value_parser = in_range(FPS_RANGE))]
+    pub fps: usize,
+
+    /// Frames between checksum comparisons with the other players, 0 turns them off
+    #[clap(long, env, default_value = "1", value_parser = in_range(DESYNC_INTERVAL_RANGE))]
+    pub desync_interval: u32,
+
+    /// Milliseconds without hearing from a player before they get disconnected
+    #[clap(long, env, default_value = "2000", value_parser = in_range(DISCONNECT_TIMEOUT_RANGE))]
+    pub disconnect_timeout: u64,
+
+    /// Milliseconds without hearing from a player before the connection counts as interrupted
+    #[clap(long, env, default_value = "500", value_parser = in_range(DISCONNECT_NOTIFY_RANGE))]
+    pub disconnect_notify_delay: u64,
+
+    /// Frames a spectated match plays behind the players
+    #[clap(long, env, default_value = "15", value_parser = in_range(SPECTATOR_DELAY_RANGE))]
+    pub spectator_delay: usize,
+}
+
+/// Parses a setting, refusing values outside of the range the settings screen allows with a
+/// usage error rather than a crash
+fn in_range<T>(range: RangeInclusive<T>) -> RangedU64ValueParser<T>
+where
+    T: TryFrom<u64> + Copy + Send + Sync + 'static,
+    <T as TryFrom<u64>>::Error: std::error::Error + Send + Sync + 'static,
+    u64: TryFrom<T>,
+{
+    let bound = |value: T| u64::try_from(value).unwrap_or(u64::MAX);
+    RangedU64ValueParser::new().range(bound(*range.start())..=bound(*range.end()))
 }
 
 impl Default for Args {
@@ -0,0 +1,142 @@
+use crate::{combat::LastImpact, in_match, stage::Stage, MainCamera, Player};
+use bevy::{prelude::*, render::camera::ScalingMode, window::PrimaryWindow};
+use bevy_xpbd_2d::prelude::*;
+
+/// Furthest the camera gets pushed from its resting spot while shaking
+const MAX_SHAKE_OFFSET: f32 = 12.0;
+/// How much trauma a single frame of hitstop adds
+const TRAUMA_PER_HITSTOP_FRAME: f32 = 0.08;
+/// Trauma lost per second
+const TRAUMA_DECAY: f32 = 2.0;
+
+/// Narrowest and widest the camera's view gets, in world units
+const MIN_VIEW_WIDTH: f32 = 600.0;
+const MAX_VIEW_WIDTH: f32 = 1000.0;
+/// Space kept between each fighter and the edge of the screen
+const VIEW_MARGIN: f32 = 150.0;
+/// How far above the fighters the camera looks, so the floor stays in view
+const VIEW_HEIGHT_OFFSET: f32 = 100.0;
+/// How quickly the camera catches up with the fighters
+const FOLLOW_SPEED: f32 = 6.0;
+
+/// Furthest apart the fighters can get on `stage` while both still fit on screen, the view
+/// never gets wider than the stage. Part of the simulation, so it's worked out from the stage
+/// rather than read back from the camera.
+pub fn max_fighter_distance(stage: &Stage) -> f32 {
+    MAX_VIEW_WIDTH.min(stage.bounds().width()) - 2.0 * VIEW_MARGIN
+}
+
+/// Screen shake is purely cosmetic so it lives outside of the rollback state,
+/// it only looks at the last impact frame to know when a new hit happened.
+#[derive(Resource, Default)]
+struct ScreenShake {
+    trauma: f32,
+    seen_impact: Option<usize>,
+}
+
+/// Where the camera is looking before shake is added on top, also purely cosmetic
+#[derive(Resource)]
+struct CameraFocus {
+    center: Vec2,
+    view_width: f32,
+}
+
+impl Default for CameraFocus {
+    fn default() -> Self {
+        CameraFocus {
+            center: Vec2::ZERO,
+            view_width: MAX_VIEW_WIDTH,
+        }
+    }
+}
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScreenShake>()
+            .init_resource::<CameraFocus>()
+            .add_systems(
+                Update,
+                (follow_fighters, screen_shake).chain().run_if(in_match),
+            );
+    }
+}
+
+/// Centers the camera between the fighters, zooming out as they get further apart
+/// without ever showing anything past the edges of the stage
+fn follow_fighters(
+    time: Res<Time>,
+    stage: Res<Stage>,
+    mut focus: ResMut<CameraFocus>,
+    fighters: Query<&Position, With<Player>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut camera: Query<&mut OrthographicProjection, With<MainCamera>>,
+) {
+    let positions: Vec<Vec2> = fighters.iter().map(|position| position.0).collect();
+    if positions.is_empty() {
+        return;
+    }
+    let min = positions.iter().fold(Vec2::MAX, |min, p| min.min(*p));
+    let max = positions.iter().fold(Vec2::MIN, |max, p| max.max(*p));
+
+    let aspect = windows
+        .get_single()
+        .map_or(16.0 / 9.0, |window| window.width() / window.height());
+    let bounds = stage.bounds();
+    let view_width = (max.x - min.x + 2.0 * VIEW_MARGIN)
+        .clamp(MIN_VIEW_WIDTH, MAX_VIEW_WIDTH)
+        .min(bounds.width());
+    let half_view = Vec2::new(view_width, view_width / aspect) * 0.5;
+
+    // keep the view inside the stage, centering it on any axis the stage is too small to fill
+    let target = (min + max) * 0.5 + Vec2::Y * VIEW_HEIGHT_OFFSET;
+    let low = bounds.min + half_view;
+    let high = bounds.max - half_view;
+    let target = Vec2::new(
+        if low.x <= high.x {
+            target.x.clamp(low.x, high.x)
+        } else {
+            bounds.center().x
+        },
+        if low.y <= high.y {
+            target.y.clamp(low.y, high.y)
+        } else {
+            bounds.center().y
+        },
+    );
+
+    let t = 1.0 - (-FOLLOW_SPEED * time.delta_seconds()).exp();
+    focus.center = focus.center.lerp(target, t);
+    focus.view_width += (view_width - focus.view_width) * t;
+
+    for mut projection in &mut camera {
+        projection.scaling_mode = ScalingMode::FixedHorizontal(focus.view_width);
+    }
+}
+
+fn screen_shake(
+    time: Res<Time>,
+    last_impact: Res<LastImpact>,
+    focus: Res<CameraFocus>,
+    mut shake: ResMut<ScreenShake>,
+    mut camera: Query<&mut Transform, With<MainCamera>>,
+) {
+    // a rollback that replays the same hit keeps the same frame, so it won't shake twice
+    if last_impact.frame != shake.seen_impact {
+        shake.seen_impact = last_impact.frame;
+        shake.trauma =
+            (shake.trauma + last_impact.hitstop as f32 * TRAUMA_PER_HITSTOP_FRAME).min(1.0);
+    }
+    shake.trauma = (shake.trauma - TRAUMA_DECAY * time.delta_seconds()).max(0.0);
+
+    // squaring makes small amounts of trauma barely noticeable
+    let strength = shake.trauma * shake.trauma * MAX_SHAKE_OFFSET;
+    let t = time.elapsed_seconds() * 60.0;
+    let offset = Vec2::new(t.sin(), (t * 1.3).cos()) * strength;
+
+    for mut transform in &mut camera {
+        transform.translation.x = focus.center.x + offset.x;
+        transform.translation.y = focus.center.y + offset.y;
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{
+    combat::{HitboxData, Hurtbox},
+    input::*,
+    projectile::ProjectileData,
+    pushbox::Pushbox,
+};
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::cmp::Reverse;
+
+/// Character files are baked into the binary so every peer runs the exact same data,
+/// loading them through the asset server could leave one side with a different version.
+pub const CHARACTER_FILES: &[(&str, &str)] =
+    &[("redNinja", include_str!("../assets/fighters/redNinja.ron"))];
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DashData {
+    /// Frames between two forward taps for them to count as a double tap
+    pub double_tap_window: usize,
+    pub dash_speed: f32,
+    pub dash_frames: u32,
+    /// Speed kept up while forward is held after a dash
+    pub run_speed: f32,
+    pub backdash_speed: f32,
+    pub backdash_frames: u32,
+    /// Frames at the start of a backdash where strikes and throws go through
+    pub backdash_invuln: u32,
+    pub air_dash_speed: f32,
+    pub air_dash_frames: u32,
+}
+
+/// Attack buttons a move can be bound to
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Punch,
+    Kick,
+    Slash,
+    Heavy,
+}
+
+impl Button {
+    pub fn just_pressed(self, buttons: u16) -> bool {
+        let mask = match self {
+            Button::Punch => INPUT_PUNCH_JUST_PRESSED,
+            Button::Kick => INPUT_KICK_JUST_PRESSED,
+            Button::Slash => INPUT_SLASH_JUST_PRESSED,
+            Button::Heavy => INPUT_HEAVY_JUST_PRESSED,
+        };
+        buttons & mask != 0
+    }
+}
+
+/// What the fighter has to be doing for a move to come out
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stance {
+    Standing,
+    Crouching,
+    /// Either standing or crouching
+    Grounded,
+    Air,
+}
+
+impl Stance {
+    pub fn allows(self, current: Stance) -> bool {
+        self == current
+            || (self == Stance::Grounded && matches!(current, Stance::Standing | Stance::Crouching))
+    }
+}
+
+/// Decides which cancel rule applies when cancelling into a move,
+/// and which move wins when several match the same input
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MoveKind {
+    #[default]
+    Normal,
+    Special,
+    Super,
+}
+
+/// How an attack has to be blocked
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Guard {
+    /// Blockable standing or crouching
+    #[default]
+    Mid,
+    /// Has to be blocked crouching
+    Low,
+    /// Has to be blocked standing
+    Overhead,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MoveData {
+    pub name: String,
+    #[serde(default)]
+    pub kind: MoveKind,
+    pub button: Button,
+    /// Direction motion that has to come before the button, if any
+    #[serde(default)]
+    pub motion: Option<Motion>,
+    pub stance: Stance,
+    pub startup: u32,
+    pub active: u32,
+    pub recovery: u32,
+    /// First and last sprite sheet index, played evenly over the length of the move
+    pub animation: (usize, usize),
+    #[serde(default)]
+    pub hitbox: Option<HitboxData>,
+    #[serde(default)]
+    pub guard: Guard,
+    /// Thrown on the first active frame
+    #[serde(default)]
+    pub projectile: Option<ProjectileData>,
+    /// First and last frame of the move (inclusive) that it can be cancelled on.
+    /// Cancels only work once the move connected, on hit or on block.
+    #[serde(default)]
+    pub cancel_window: Option<(u32, u32)>,
+    /// Names of normals this move can chain into
+    #[serde(default)]
+    pub chains: Vec<String>,
+    #[serde(default)]
+    pub special_cancel: bool,
+    #[serde(default)]
+    pub super_cancel: bool,
+    #[serde(default)]
+    pub jump_cancel: bool,
+    /// Super meter spent to perform the move
+    #[serde(default)]
+    pub meter_cost: u32,
+    /// Frames the whole simulation freezes for when the move starts
+    #[serde(default)]
+    pub super_freeze: u32,
+}
+
+impl MoveData {
+    pub fn total_frames(&self) -> u32 {
+        self.startup + self.active + self.recovery
+    }
+
+    pub fn is_active(&self, frame: u32) -> bool {
+        frame >= self.startup && frame < self.startup + self.active
+    }
+
+    pub fn in_cancel_window(&self, frame: u32) -> bool {
+        self.cancel_window
+            .map_or(false, |(first, last)| frame >= first && frame <= last)
+    }
+
+    /// Whether the cancel rules of this move allow going into `next`
+    pub fn can_cancel_into(&self, next: &MoveData) -> bool {
+        match next.kind {
+            MoveKind::Normal => self.chains.contains(&next.name),
+            MoveKind::Special => self.special_cancel,
+            MoveKind::Super => self.super_cancel,
+        }
+    }
+
+    pub fn sprite_index(&self, frame: u32) -> usize {
+        let (first, last) = self.animation;
+        let sprites = last - first + 1;
+        first + (frame as usize * sprites / self.total_frames() as usize).min(sprites - 1)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CharacterData {
+    pub name: String,
+    pub sprite_sheet: String,
+    pub tile_size: (f32, f32),
+    pub columns: usize,
+    pub rows: usize,
+    pub idle_sprite: usize,
+    pub crouch_sprite: usize,
+    /// Colors the sprite sheet can be tinted with, picked on character select
+    #[serde(default = "CharacterData::default_palettes")]
+    pub palettes: Vec<(f32, f32, f32)>,
+    pub standing_hurtbox: Hurtbox,
+    pub crouching_hurtbox: Hurtbox,
+    pub pushbox: Pushbox,
+    /// Speed added each frame while walking
+    pub walk_acceleration: f32,
+    /// Top walking speed
+    pub walk_speed: f32,
+    pub jump_velocity: f32,
+    pub dash: DashData,
+    pub moves: Vec<MoveData>,
+}
+
+impl CharacterData {
+    fn default_palettes() -> Vec<(f32, f32, f32)> {
+        vec![(1.0, 1.0, 1.0)]
+    }
+
+    pub fn palette_color(&self, palette: usize) -> Color {
+        let (r, g, b) = self.palettes[palette % self.palettes.len()];
+        Color::rgb(r, g, b)
+    }
+
+    /// Finds the move that was just input for the stance the fighter is in.
+    /// Supers win over specials and specials over normals when more than one matches.
+    /// Moves costing more than the available `meter` are skipped.
+    pub fn find_move(
+        &self,
+        history: &InputHistory,
+        facing_left: bool,
+        stance: Stance,
+        meter: u32,
+    ) -> Option<usize> {
+        let buttons = history.get(0);
+        self.moves
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| {
+                m.stance.allows(stance)
+                    && m.meter_cost <= meter
+                    && m.button.just_pressed(buttons)
+                    && m.motion
+                        .map_or(true, |motion| motion.performed(history, facing_left))
+            })
+            .min_by_key(|(_, m)| Reverse(m.kind))
+            .map(|(index, _)| index)
+    }
+
+    /// Makes sure there is a palette, every move can be animated and cancelled within its length,
+    /// and every chain points at a normal that exists
+    fn validate(&self) -> Result<(), String> {
+        if self.palettes.is_empty() {
+            return Err("needs at least one palette".to_string());
+        }
+        for m in &self.moves {
+            // `sprite_index` divides by the length of the move and counts the sprites in between
+            if m.total_frames() == 0 {
+                return Err(format!("{} lasts no frames", m.name));
+            }
+            let (first, last) = m.animation;
+            if first > last {
+                return Err(format!(
+                    "{} animates from sprite {first} back to {last}",
+                    m.name
+                ));
+            }
+            if let Some((first, last)) = m.cancel_window {
+                if first > last || last >= m.total_frames() {
+                    return Err(format!(
+                        "{} can be cancelled on frames {first} to {last}, but lasts {} frames",
+                        m.name,
+                        m.total_frames()
+                    ));
+                }
+            }
+            for chain in &m.chains {
+                let target = self.moves.iter().find(|other| &other.name == chain);
+                match target {
+                    Some(other) if other.kind == MoveKind::Normal => {}
+                    Some(_) => {
+                        return Err(format!(
+                            "{} chains into {chain}, which isn't a normal",
+                            m.name
+                        ))
+                    }
+                    None => return Err(format!("{} chains into unknown move {chain}", m.name)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every playable character, indexed by `CharacterId`
+#[derive(Resource, Debug)]
+pub struct Characters(pub Vec<CharacterData>);
+
+impl Default for Characters {
+    fn default() -> Self {
+        let characters = CHARACTER_FILES
+            .iter()
+            .map(|(file, contents)| {
+                let character: CharacterData = ron::from_str(contents)
+                    .unwrap_or_else(|err| panic!("failed to parse character {file}: {err}"));
+                if let Err(err) = character.validate() {
+                    panic!("invalid character {file}: {err}");
+                }
+                character
+            })
+            .collect();
+        Characters(characters)
+    }
+}
+
+impl Characters {
+    pub fn get(&self, id: &CharacterId) -> &CharacterData {
+        &self.0[id.0]
+    }
+
+    pub fn find(&self, name: &str) -> Option<CharacterId> {
+        self.0
+            .iter()
+            .position(|character| character.name == name)
+            .map(CharacterId)
+    }
+}
+
+/// Which character from `Characters` a fighter is playing as
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CharacterId(pub usize);
@@ -0,0 +1,395 @@
+use crate::{
+    character::{CharacterId, Characters},
+    direct::DirectSocket,
+    lobby::{LobbyInbox, LobbyMessage, LobbyNotice, MatchPeers},
+    AppState, PLAYERS,
+};
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+
+const PLAYER_COLORS: [Color; PLAYERS] = [Color::rgb(0.9, 0.25, 0.2), Color::rgb(0.2, 0.45, 0.9)];
+const TILE_COLOR: Color = Color::rgb(0.25, 0.25, 0.3);
+const BOTH_CURSORS_COLOR: Color = Color::rgb(0.6, 0.35, 0.6);
+
+/// What a player picked on character select
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FighterSelection {
+    pub character: CharacterId,
+    pub palette: usize,
+}
+
+/// Picks of every player by handle, read when spawning the fighters
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct Selections(pub [FighterSelection; PLAYERS]);
+
+/// Cursor and ready state of both players while the select screen is open
+#[derive(Resource, Default)]
+struct SelectScreen {
+    cursors: [FighterSelection; PLAYERS],
+    ready: [bool; PLAYERS],
+    /// Handles picked with this keyboard, one after the other when there's more than one
+    local: Vec<usize>,
+    /// Remote peer for each handle that isn't local
+    remote: Vec<(PeerId, usize)>,
+}
+
+impl SelectScreen {
+    /// The local handle currently being picked for, if any are left
+    fn active(&self) -> Option<usize> {
+        self.local
+            .iter()
+            .copied()
+            .find(|handle| !self.ready[*handle])
+    }
+}
+
+/// Marker component for character select UI
+#[derive(Component)]
+struct CharacterSelectUI;
+
+#[derive(Component)]
+struct CharacterTile(CharacterId);
+
+/// Name, palette and ready state of the player with this handle
+#[derive(Component)]
+struct PlayerPanelText(usize);
+
+#[derive(Component)]
+struct PaletteSwatch(usize);
+
+pub struct CharacterSelectPlugin;
+
+impl Plugin for CharacterSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selections>()
+            .add_systems(
+                OnEnter(AppState::CharacterSelect),
+                (start_character_select, character_select_startup).chain(),
+            )
+            .add_systems(
+                Update,
+                (
+                    character_select_input,
+                    watch_peers.run_if(resource_exists::<MatchboxSocket<MultipleChannels>>()),
+                    receive_picks.run_if(resource_exists::<MatchPeers>()),
+                    update_character_select_ui,
+                    finish_character_select,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::CharacterSelect)),
+            )
+            .add_systems(OnExit(AppState::CharacterSelect), character_select_cleanup);
+    }
+}
+
+fn start_character_select(mut commands: Commands, peers: Option<Res<MatchPeers>>) {
+    let mut screen = SelectScreen::default();
+    match peers {
+        // online everyone picks for themselves, and spectators only watch the picks come in
+        Some(peers) => {
+            for (handle, &peer) in peers.players.iter().enumerate() {
+                if peer == peers.local {
+                    screen.local.push(handle);
+                } else {
+                    screen.remote.push((peer, handle));
+                }
+            }
+        }
+        // offline the keyboard picks for both sides
+        None => screen.local = vec![0, 1],
+    }
+    // player 2 starts on another palette so a mirror match can be told apart
+    screen.cursors[1].palette = 1;
+    commands.insert_resource(screen);
+}
+
+fn character_select_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    characters: Res<Characters>,
+) {
+    let font = asset_server.load("fonts/quicksand-light.ttf");
+    let text_style = |font_size: f32| TextStyle {
+        font: font.clone(),
+        font_size,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::SpaceEvenly,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            background_color: Color::rgb(0.1, 0.1, 0.14).into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Character Select",
+                text_style(64.0),
+            ));
+
+            // the roster, each tile gets colored by the cursors on it
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(16.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (index, character) in characters.0.iter().enumerate() {
+                        parent
+                            .spawn((
+                                NodeBundle {
+                                    style: Style {
+                                        width: Val::Px(180.0),
+                                        height: Val::Px(80.0),
+                                        justify_content: JustifyContent::Center,
+                                        align_items: AlignItems::Center,
+                                        ..default()
+                                    },
+                                    background_color: TILE_COLOR.into(),
+                                    ..default()
+                                },
+                                CharacterTile(CharacterId(index)),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    character.name.clone(),
+                                    text_style(28.0),
+                                ));
+                            });
+                    }
+                });
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Percent(80.0),
+                        justify_content: JustifyContent::SpaceBetween,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for handle in 0..PLAYERS {
+                        parent
+                            .spawn(NodeBundle {
+                                style: Style {
+                                    flex_direction: FlexDirection::Column,
+                                    align_items: AlignItems::Center,
+                                    row_gap: Val::Px(8.0),
+                                    ..default()
+                                },
+                                ..default()
+                            })
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    TextBundle::from_section("", text_style(32.0)),
+                                    PlayerPanelText(handle),
+                                ));
+                                parent.spawn((
+                                    NodeBundle {
+                                        style: Style {
+                                            width: Val::Px(64.0),
+                                            height: Val::Px(64.0),
+                                            ..default()
+                                        },
+                                        ..default()
+                                    },
+                                    PaletteSwatch(handle),
+                                ));
+                            });
+                    }
+                });
+
+            parent.spawn(TextBundle::from_section(
+                "A/D pick a character, W/S pick a color, U to ready up, I to change your mind",
+                text_style(24.0),
+            ));
+        })
+        .insert(CharacterSelectUI);
+}
+
+fn character_select_input(
+    keys: Res<Input<KeyCode>>,
+    characters: Res<Characters>,
+    mut screen: ResMut<SelectScreen>,
+    mut socket: Option<ResMut<MatchboxSocket<MultipleChannels>>>,
+    mut direct: Option<ResMut<DirectSocket>>,
+) {
+    let online = socket.is_some() || direct.is_some();
+    // backing out un-readies the last local pick, so offline player 1 can go back to picking
+    if keys.just_pressed(KeyCode::I) {
+        let last_ready = screen
+            .local
+            .iter()
+            .rev()
+            .copied()
+            .find(|h| screen.ready[*h]);
+        // online a pick can't be taken back once the other side has it
+        if let Some(handle) = last_ready.filter(|_| !online) {
+            screen.ready[handle] = false;
+        }
+        return;
+    }
+
+    let Some(handle) = screen.active() else {
+        return;
+    };
+    let character_count = characters.0.len();
+    let cursor = &mut screen.cursors[handle];
+    if keys.just_pressed(KeyCode::A) {
+        cursor.character =
+            CharacterId((cursor.character.0 + character_count - 1) % character_count);
+    }
+    if keys.just_pressed(KeyCode::D) {
+        cursor.character = CharacterId((cursor.character.0 + 1) % character_count);
+    }
+    let palette_count = characters.get(&cursor.character).palettes.len();
+    if keys.just_pressed(KeyCode::W) {
+        cursor.palette = (cursor.palette + palette_count - 1) % palette_count;
+    }
+    if keys.just_pressed(KeyCode::S) {
+        cursor.palette = (cursor.palette + 1) % palette_count;
+    }
+    cursor.palette %= palette_count;
+
+    if keys.just_pressed(KeyCode::U) {
+        let pick = *cursor;
+        screen.ready[handle] = true;
+        let character = characters.get(&pick.character).name.clone();
+        if let Some(socket) = socket.as_mut() {
+            LobbyMessage::CharacterPick {
+                character,
+                palette: pick.palette,
+            }
+            .broadcast(socket);
+        } else if let Some(direct) = direct.as_mut() {
+            // goes out with the next hellos
+            direct.pick = Some((character, pick.palette));
+        }
+    }
+}
+
+/// Goes back to the lobby when a player leaves before the match starts, spectators can come and go
+pub fn watch_peers(
+    mut commands: Commands,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+    peers: Res<MatchPeers>,
+    mut inbox: ResMut<LobbyInbox>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    for (peer, new_state) in socket.update_peers() {
+        if matches!(new_state, PeerState::Disconnected) && peers.players.contains(&peer) {
+            info!("peer {peer} disconnected before the match started");
+            // picks made so far are made again after the lobby
+            inbox.0.clear();
+            commands.insert_resource(LobbyNotice(
+                "A player left before the match started".to_string(),
+            ));
+            app_state.set(AppState::Lobby);
+        }
+    }
+}
+
+fn receive_picks(
+    characters: Res<Characters>,
+    mut screen: ResMut<SelectScreen>,
+    mut inbox: ResMut<LobbyInbox>,
+) {
+    let screen = &mut *screen;
+    inbox.0.retain(|(peer, message)| {
+        let LobbyMessage::CharacterPick { character, palette } = message else {
+            return true;
+        };
+        let Some(&(_, handle)) = screen.remote.iter().find(|(remote, _)| remote == peer) else {
+            warn!("character pick from unknown peer {peer}");
+            return false;
+        };
+        let Some(character) = characters.find(character) else {
+            warn!("peer {peer} picked unknown character {character}");
+            return false;
+        };
+        info!("peer {peer} picked {character:?}");
+        screen.cursors[handle] = FighterSelection {
+            character,
+            palette: *palette,
+        };
+        screen.ready[handle] = true;
+        false
+    });
+}
+
+fn update_character_select_ui(
+    characters: Res<Characters>,
+    screen: Res<SelectScreen>,
+    mut tiles: Query<(&CharacterTile, &mut BackgroundColor), Without<PaletteSwatch>>,
+    mut panels: Query<(&PlayerPanelText, &mut Text)>,
+    mut swatches: Query<(&PaletteSwatch, &mut BackgroundColor), Without<CharacterTile>>,
+) {
+    for (tile, mut color) in &mut tiles {
+        let on_tile = |handle: usize| screen.cursors[handle].character == tile.0;
+        color.0 = match (on_tile(0), on_tile(1)) {
+            (true, true) => BOTH_CURSORS_COLOR,
+            (true, false) => PLAYER_COLORS[0],
+            (false, true) => PLAYER_COLORS[1],
+            (false, false) => TILE_COLOR,
+        };
+    }
+
+    for (panel, mut text) in &mut panels {
+        let handle = panel.0;
+        let cursor = screen.cursors[handle];
+        let status = if screen.ready[handle] {
+            "Ready!"
+        } else if screen.local.contains(&handle) {
+            "Picking..."
+        } else {
+            "Waiting for pick..."
+        };
+        text.sections[0].value = format!(
+            "P{}: {}\n{status}",
+            handle + 1,
+            characters.get(&cursor.character).name
+        );
+        text.sections[0].style.color = PLAYER_COLORS[handle];
+    }
+
+    for (swatch, mut color) in &mut swatches {
+        let cursor = screen.cursors[swatch.0];
+        color.0 = characters
+            .get(&cursor.character)
+            .palette_color(cursor.palette);
+    }
+}
+
+/// Moves on to the match once every pick is in, both peers end up with the same picks
+fn finish_character_select(
+    mut commands: Commands,
+    mut app_state: ResMut<NextState<AppState>>,
+    screen: Res<SelectScreen>,
+) {
+    if screen.ready.iter().any(|ready| !ready) {
+        return;
+    }
+    info!("everyone picked, going in-game");
+    commands.insert_resource(Selections(screen.cursors));
+    // transition to in-game state, the session is started once the scene has been spawned
+    app_state.set(AppState::InGame);
+}
+
+fn character_select_cleanup(query: Query<Entity, With<CharacterSelectUI>>, mut commands: Commands) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<SelectScreen>();
+}
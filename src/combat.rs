@@ -0,0 +1,512 @@
+use crate::{
+    character::{CharacterId, Characters, Guard},
+    input::*,
+    meter::SuperMeter,
+    movement::Crouching,
+    FrameCount, GgrsConfig, Player,
+};
+use bevy::prelude::*;
+use bevy_ggrs::PlayerInputs;
+use bevy_xpbd_2d::{math::*, prelude::*};
+use serde::Deserialize;
+
+pub const MAX_HEALTH: u32 = 1000;
+
+/// Frames a fighter can't be thrown for after blockstun ends
+pub const BLOCKSTUN_THROW_INVULN: u32 = 5;
+/// Frames a fighter can't be thrown for after getting up from a knockdown
+pub const WAKEUP_THROW_INVULN: u32 = 8;
+/// Length of the get-up after a knockdown, the fighter is invulnerable for all of it
+pub const WAKEUP_FRAMES: u32 = 20;
+/// Knockdown frames left after a quick rise
+pub const QUICK_RISE_FRAMES: u32 = 6;
+/// Extra frames spent on the floor with a delayed wakeup
+pub const DELAYED_WAKEUP_FRAMES: u32 = 20;
+
+/// Describes the hitbox of an attack, offset is for a fighter facing right
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct HitboxData {
+    pub offset: Vec2,
+    pub size: Vec2,
+    pub damage: u32,
+    /// How many simulation frames both fighters freeze for when it connects
+    pub hitstop: u32,
+    pub hitstun: u32,
+    pub blockstun: u32,
+    /// Frames the defender stays on the floor for when the hit isn't blocked,
+    /// moves without it only put the defender in hitstun
+    #[serde(default)]
+    pub knockdown: Option<u32>,
+}
+
+/// What a fighter is currently doing, anything other than `Idle` locks out normal input
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component, Hash)]
+pub enum FighterState {
+    #[default]
+    Idle,
+    Hitstun {
+        frames: u32,
+    },
+    Blockstun {
+        frames: u32,
+    },
+    /// Lying on the floor, strikes and throws pass through until the fighter is back up
+    Knockdown {
+        frames: u32,
+        /// Whether a quick rise or delayed wakeup was already picked
+        chosen: bool,
+    },
+    /// Getting up after a knockdown, invulnerable until it's over
+    Wakeup {
+        frames: u32,
+    },
+    /// Holding the player with the `victim` handle
+    Throwing {
+        frame: u32,
+        victim: usize,
+    },
+    /// Being held by the player with the `thrower` handle
+    Thrown {
+        frame: u32,
+        thrower: usize,
+    },
+    /// Both fighters got pushed apart after a throw was broken
+    ThrowTeched {
+        frames: u32,
+    },
+}
+
+impl FighterState {
+    /// Fighters lying on the floor can't be hit, the wakeup after it is covered by `Invulnerable`
+    pub fn knocked_down(&self) -> bool {
+        matches!(self, FighterState::Knockdown { .. })
+    }
+}
+
+/// Frames left where the fighter can't be grabbed
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct ThrowInvuln {
+    pub frames: u32,
+}
+
+/// Frames left where strikes, projectiles and throws all pass through the fighter
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Invulnerable {
+    pub frames: u32,
+}
+
+impl Invulnerable {
+    pub fn active(&self) -> bool {
+        self.frames > 0
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, Reflect, Hash)]
+#[reflect(Component, Hash)]
+pub struct Health(pub u32);
+
+/// Area a fighter can be hit in, offset from its position
+#[derive(Component, Deserialize, Clone, Copy, Debug)]
+pub struct Hurtbox {
+    pub offset: Vec2,
+    pub size: Vec2,
+}
+
+impl Hurtbox {
+    pub fn aabb(&self, position: Vector) -> Aabb {
+        Aabb {
+            center: position + self.offset,
+            size: self.size,
+        }
+    }
+}
+
+/// The move a fighter is performing, if any
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component, Hash)]
+pub struct AttackState {
+    /// Index into the character's move list
+    pub current: Option<usize>,
+    /// Frames since the move started
+    pub frame: u32,
+    /// Whether the move already connected, so it only hits once
+    pub connected: bool,
+}
+
+impl AttackState {
+    pub fn attacking(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn start(&mut self, move_index: usize) {
+        *self = AttackState {
+            current: Some(move_index),
+            frame: 0,
+            connected: false,
+        };
+    }
+
+    pub fn stop(&mut self) {
+        *self = AttackState::default();
+    }
+}
+
+/// Freezes a fighter in place while frames is above 0.
+/// The velocity it had when the hit landed is given back once it runs out.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Hitstop {
+    pub frames: u32,
+    pub position: Vector,
+    pub velocity: Vector,
+}
+
+impl Hitstop {
+    pub fn active(&self) -> bool {
+        self.frames > 0
+    }
+}
+
+/// Frame the last hit landed on, read by the camera to know when to shake
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct LastImpact {
+    pub frame: Option<usize>,
+    pub hitstop: u32,
+}
+
+/// Frame a fighter ran out of health on. Part of the rollback state, since a predicted
+/// knockout can still be taken back, the match only ends once the frame is confirmed.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct KnockOut {
+    pub frame: Option<usize>,
+}
+
+pub fn check_knockout(
+    frame_count: Res<FrameCount>,
+    mut knockout: ResMut<KnockOut>,
+    fighters: Query<&Health, With<Player>>,
+) {
+    if knockout.frame.is_none() && fighters.iter().any(|health| health.0 == 0) {
+        knockout.frame = Some(frame_count.frame);
+    }
+}
+
+/// Axis aligned box used for hit checks
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub center: Vec2,
+    pub size: Vec2,
+}
+
+impl Aabb {
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        let distance = (self.center - other.center).abs();
+        let reach = (self.size + other.size) * 0.5;
+        distance.x < reach.x && distance.y < reach.y
+    }
+}
+
+/// Gets the world space box of a hitbox, mirrored when the fighter faces left
+pub fn hitbox_aabb(position: Vector, facing_left: bool, hitbox: &HitboxData) -> Aabb {
+    let facing = if facing_left { -1.0 } else { 1.0 };
+    Aabb {
+        center: position + Vec2::new(hitbox.offset.x * facing, hitbox.offset.y),
+        size: hitbox.size,
+    }
+}
+
+/// Holds fighters in hitstop where they were when the hit landed.
+/// Runs right after the physics step so gravity and momentum get undone.
+pub fn apply_hitstop(mut query: Query<(&mut Hitstop, &mut Position, &mut LinearVelocity)>) {
+    for (mut hitstop, mut position, mut velocity) in &mut query {
+        if !hitstop.active() {
+            continue;
+        }
+        hitstop.frames -= 1;
+        position.0 = hitstop.position;
+        velocity.0 = if hitstop.active() {
+            Vector::ZERO
+        } else {
+            hitstop.velocity
+        };
+    }
+}
+
+/// Counts down stun and knockdown, returning the fighter to `Idle` when they run out.
+/// Pressing Punch while knocked down quick rises and pressing Kick delays the wakeup,
+/// only the first of the two counts.
+pub fn update_fighter_state(
+    mut query: Query<
+        (
+            &InputHistory,
+            &mut FighterState,
+            &mut ThrowInvuln,
+            &mut Invulnerable,
+            &Hitstop,
+        ),
+        With<Player>,
+    >,
+) {
+    for (history, mut state, mut throw_invuln, mut invulnerable, hitstop) in &mut query {
+        if hitstop.active() {
+            continue;
+        }
+        throw_invuln.frames = throw_invuln.frames.saturating_sub(1);
+        invulnerable.frames = invulnerable.frames.saturating_sub(1);
+
+        if let FighterState::Knockdown {
+            frames,
+            chosen: false,
+        } = *state
+        {
+            let buttons = history.get(0);
+            if buttons & INPUT_PUNCH_JUST_PRESSED != 0 {
+                *state = FighterState::Knockdown {
+                    frames: frames.min(QUICK_RISE_FRAMES),
+                    chosen: true,
+                };
+            } else if buttons & INPUT_KICK_JUST_PRESSED != 0 {
+                *state = FighterState::Knockdown {
+                    frames: frames + DELAYED_WAKEUP_FRAMES,
+                    chosen: true,
+                };
+            }
+        }
+
+        *state = match *state {
+            FighterState::Hitstun { frames } if frames > 1 => {
+                FighterState::Hitstun { frames: frames - 1 }
+            }
+            FighterState::Blockstun { frames } if frames > 1 => {
+                FighterState::Blockstun { frames: frames - 1 }
+            }
+            FighterState::Knockdown { frames, chosen } if frames > 1 => FighterState::Knockdown {
+                frames: frames - 1,
+                chosen,
+            },
+            FighterState::Wakeup { frames } if frames > 1 => {
+                FighterState::Wakeup { frames: frames - 1 }
+            }
+            FighterState::ThrowTeched { frames } if frames > 1 => {
+                FighterState::ThrowTeched { frames: frames - 1 }
+            }
+            FighterState::Blockstun { .. } => {
+                throw_invuln.frames = BLOCKSTUN_THROW_INVULN;
+                FighterState::Idle
+            }
+            FighterState::Knockdown { .. } => {
+                invulnerable.frames = WAKEUP_FRAMES;
+                FighterState::Wakeup {
+                    frames: WAKEUP_FRAMES,
+                }
+            }
+            FighterState::Wakeup { .. } => {
+                throw_invuln.frames = WAKEUP_THROW_INVULN;
+                FighterState::Idle
+            }
+            FighterState::Hitstun { .. } | FighterState::ThrowTeched { .. } => FighterState::Idle,
+            other => other,
+        };
+    }
+}
+
+/// Whether the defender is holding away from where the attack is coming from
+pub fn holding_back(buttons: u16, defender_x: f32, attack_x: f32) -> bool {
+    if attack_x > defender_x {
+        buttons & INPUT_LEFT != 0
+    } else {
+        buttons & INPUT_RIGHT != 0
+    }
+}
+
+/// Blocking is only possible while standing around or already blocking, not mid attack
+pub fn can_block(state: &FighterState, attack: &AttackState) -> bool {
+    matches!(state, FighterState::Idle | FighterState::Blockstun { .. }) && !attack.attacking()
+}
+
+/// Lows have to be blocked crouching and overheads standing
+pub fn guards_against(guard: Guard, crouching: bool) -> bool {
+    match guard {
+        Guard::Mid => true,
+        Guard::Low => crouching,
+        Guard::Overhead => !crouching,
+    }
+}
+
+/// Applies damage and stun to the defender of a hit, knocking it down if the hit does that
+pub fn land_hit(
+    hitbox: &HitboxData,
+    blocked: bool,
+    health: &mut Health,
+    state: &mut FighterState,
+    attack: &mut AttackState,
+) {
+    if blocked {
+        *state = FighterState::Blockstun {
+            frames: hitbox.blockstun,
+        };
+    } else {
+        health.0 = health.0.saturating_sub(hitbox.damage);
+        info!(
+            "hit landed for {} damage, {} health left",
+            hitbox.damage, health.0
+        );
+        *state = match hitbox.knockdown {
+            Some(frames) => FighterState::Knockdown {
+                frames,
+                chosen: false,
+            },
+            None => FighterState::Hitstun {
+                frames: hitbox.hitstun,
+            },
+        };
+    }
+
+    // getting hit interrupts whatever attack was playing
+    attack.stop();
+}
+
+/// Moves attacks along by a frame, ending them once their recovery is over
+pub fn advance_attacks(
+    characters: Res<Characters>,
+    mut query: Query<(&CharacterId, &FighterState, &Hitstop, &mut AttackState)>,
+) {
+    for (character, state, hitstop, mut attack) in &mut query {
+        let Some(move_index) = attack.current else {
+            continue;
+        };
+        if hitstop.active() || *state != FighterState::Idle {
+            continue;
+        }
+        attack.frame += 1;
+        if attack.frame >= characters.get(character).moves[move_index].total_frames() {
+            attack.stop();
+        }
+    }
+}
+
+pub fn detect_hits(
+    frame_count: Res<FrameCount>,
+    characters: Res<Characters>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut last_impact: ResMut<LastImpact>,
+    mut meter: ResMut<SuperMeter>,
+    mut fighters: Query<(
+        Entity,
+        &Player,
+        &CharacterId,
+        &Position,
+        &LinearVelocity,
+        &Hurtbox,
+        &Invulnerable,
+        &Crouching,
+        &TextureAtlasSprite,
+        &mut Health,
+        &mut Hitstop,
+        &mut FighterState,
+        &mut AttackState,
+    )>,
+) {
+    let mut hits = Vec::new();
+
+    for (
+        attacker,
+        attacker_player,
+        character,
+        position,
+        _,
+        _,
+        _,
+        _,
+        sprite,
+        _,
+        hitstop,
+        state,
+        attack,
+    ) in &fighters
+    {
+        let Some(move_index) = attack.current else {
+            continue;
+        };
+        let attack_move = &characters.get(character).moves[move_index];
+        let Some(move_hitbox) = attack_move.hitbox else {
+            continue;
+        };
+        // fighters frozen in hitstop can't start a new hit
+        if attack.connected
+            || hitstop.active()
+            || *state != FighterState::Idle
+            || !attack_move.is_active(attack.frame)
+        {
+            continue;
+        }
+
+        let hitbox = hitbox_aabb(position.0, sprite.flip_x, &move_hitbox);
+        for (defender, _, _, defender_position, _, hurtbox, invulnerable, .., defender_state, _) in
+            &fighters
+        {
+            if defender == attacker || invulnerable.active() || defender_state.knocked_down() {
+                continue;
+            }
+            if hitbox.overlaps(&hurtbox.aabb(defender_position.0)) {
+                hits.push((
+                    attacker,
+                    attacker_player.handle,
+                    position.0.x,
+                    defender,
+                    move_hitbox,
+                    attack_move.guard,
+                ));
+                break;
+            }
+        }
+    }
+
+    for (attacker, attacker_handle, attacker_x, defender, hitbox, guard) in hits {
+        if let Ok((.., mut attack)) = fighters.get_mut(attacker) {
+            attack.connected = true;
+        }
+
+        if let Ok((
+            _,
+            player,
+            _,
+            position,
+            _,
+            _,
+            _,
+            crouching,
+            _,
+            mut health,
+            _,
+            mut state,
+            mut attack,
+        )) = fighters.get_mut(defender)
+        {
+            let buttons = inputs.get(player.handle).map_or(0, |input| input.0.buttons);
+            let blocked = can_block(&state, &attack)
+                && holding_back(buttons, position.0.x, attacker_x)
+                && guards_against(guard, crouching.0);
+            land_hit(&hitbox, blocked, &mut health, &mut state, &mut attack);
+            meter.on_hit(attacker_handle, player.handle, hitbox.damage, blocked);
+        }
+
+        // both fighters freeze so the impact reads on screen
+        for entity in [attacker, defender] {
+            if let Ok((_, _, _, position, velocity, .., mut hitstop, _, _)) =
+                fighters.get_mut(entity)
+            {
+                *hitstop = Hitstop {
+                    frames: hitbox.hitstop,
+                    position: position.0,
+                    velocity: velocity.0,
+                };
+            }
+        }
+
+        *last_impact = LastImpact {
+            frame: Some(**frame_count),
+            hitstop: hitbox.hitstop,
+        };
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    in_match,
+    lobby::{LobbyNotice, MatchPeers},
+    menu::{despawn_menu, GameMode},
+    AppState, GgrsConfig,
+};
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs::GgrsEvent, Session};
+use bevy_matchbox::prelude::*;
+
+/// Seconds a match that ended early stays on screen before going back to the lobby
+const MATCH_ABORTED_SECONDS: f32 = 4.0;
+
+/// What the P2P or spectator session reported about the connection to the other players.
+/// Purely for showing the player, none of this is part of the rollback state.
+#[derive(Resource, Default)]
+struct ConnectionStatus {
+    /// Peers that stopped responding, with the time they get dropped at
+    interrupted: Vec<(PeerId, f32)>,
+    /// Why the match ended early, and when
+    ended: Option<(String, f32)>,
+}
+
+/// Marker component for the connection overlay
+#[derive(Component)]
+struct ConnectionUI;
+
+#[derive(Component)]
+struct ConnectionText;
+
+/// Set once the session was dropped for a disconnect or desync. The match is still on screen
+/// but must not be started up again, like on resuming from the pause menu.
+#[derive(Resource)]
+pub struct MatchEnded;
+
+pub struct ConnectionPlugin;
+
+impl Plugin for ConnectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ConnectionStatus>()
+            .add_systems(
+                OnEnter(AppState::InGame),
+                connection_startup.run_if(not(any_with_component::<ConnectionUI>())),
+            )
+            .add_systems(
+                Update,
+                (
+                    handle_session_events.run_if(resource_exists::<Session<GgrsConfig>>()),
+                    update_connection_ui,
+                    leave_aborted_match,
+                )
+                    .chain()
+                    .run_if(in_match),
+            );
+        for state in [
+            AppState::MainMenu,
+            AppState::Lobby,
+            AppState::DirectConnect,
+            AppState::CharacterSelect,
+        ] {
+            app.add_systems(OnEnter(state), despawn_menu::<ConnectionUI>);
+        }
+    }
+}
+
+fn connection_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut status: ResMut<ConnectionStatus>,
+) {
+    *status = ConnectionStatus::default();
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(40.0),
+                    justify_content: JustifyContent::Center,
+                    padding: UiRect::all(Val::Px(16.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            ConnectionUI,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/quicksand-light.ttf"),
+                        font_size: 40.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                ConnectionText,
+            ));
+        });
+}
+
+fn handle_session_events(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut session: ResMut<Session<GgrsConfig>>,
+    peers: Option<Res<MatchPeers>>,
+    mut status: ResMut<ConnectionStatus>,
+) {
+    let (events, spectating): (Vec<_>, _) = match session.as_mut() {
+        Session::P2P(session) => (session.events().collect(), false),
+        Session::Spectator(session) => (session.events().collect(), true),
+        Session::SyncTest(_) => return,
+    };
+    // spectators coming and going doesn't change anything for the players
+    let is_spectator = |addr: &PeerId| peers.as_ref().is_some_and(|p| p.spectators.contains(addr));
+    let now = time.elapsed_seconds();
+    for event in events {
+        info!("GGRS Event: {event:?}");
+        match event {
+            GgrsEvent::NetworkInterrupted { addr, .. }
+            | GgrsEvent::NetworkResumed { addr }
+            | GgrsEvent::Disconnected { addr }
+                if is_spectator(&addr) => {}
+            GgrsEvent::NetworkInterrupted {
+                addr,
+                disconnect_timeout,
+            } => {
+                status.interrupted.retain(|(peer, _)| *peer != addr);
+                let drop_at = now + disconnect_timeout as f32 / 1000.0;
+                status.interrupted.push((addr, drop_at));
+            }
+            GgrsEvent::NetworkResumed { addr } => {
+                status.interrupted.retain(|(peer, _)| *peer != addr);
+            }
+            GgrsEvent::Disconnected { addr } => {
+                status.interrupted.retain(|(peer, _)| *peer != addr);
+                let message = if spectating {
+                    "The host disconnected, there's nothing left to watch".to_string()
+                } else {
+                    // nobody won the match, it just can't go on
+                    let player = peers
+                        .as_ref()
+                        .and_then(|p| p.players.iter().position(|player| *player == addr));
+                    match player {
+                        Some(handle) => {
+                            format!("Player {} disconnected, match called off", handle + 1)
+                        }
+                        None => "A player disconnected, match called off".to_string(),
+                    }
+                };
+                status.ended = Some((message, now));
+            }
+            GgrsEvent::DesyncDetected {
+                frame,
+                local_checksum,
+                remote_checksum,
+                addr,
+            } => {
+                error!(
+                    "desynced with {addr} on frame {frame}: {local_checksum:x} != {remote_checksum:x}"
+                );
+                status.ended = Some((
+                    format!("Desynced with the other player on frame {frame}, match called off"),
+                    now,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    if status.ended.is_some() {
+        // there's nothing left to play, so the match stays frozen where it ended
+        commands.remove_resource::<Session<GgrsConfig>>();
+        commands.insert_resource(MatchEnded);
+    }
+}
+
+fn update_connection_ui(
+    time: Res<Time>,
+    status: Res<ConnectionStatus>,
+    mut overlay: Query<&mut Visibility, With<ConnectionUI>>,
+    mut text: Query<&mut Text, With<ConnectionText>>,
+) {
+    let (Ok(mut visibility), Ok(mut text)) = (overlay.get_single_mut(), text.get_single_mut())
+    else {
+        return;
+    };
+    let message = if let Some((message, _)) = &status.ended {
+        Some(message.clone())
+    } else {
+        let dropping_in = status
+            .interrupted
+            .iter()
+            .map(|(_, drop_at)| drop_at - time.elapsed_seconds())
+            .reduce(f32::min);
+        dropping_in.map(|seconds| {
+            format!(
+                "Connection interrupted, disconnecting in {:.0}s",
+                seconds.max(0.0).ceil()
+            )
+        })
+    };
+    *visibility = if message.is_some() {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    text.sections[0].value = message.unwrap_or_default();
+}
+
+/// Goes back to the lobby a little while after the match ended early
+fn leave_aborted_match(
+    mut commands: Commands,
+    time: Res<Time>,
+    mode: Res<GameMode>,
+    mut status: ResMut<ConnectionStatus>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    let Some((message, ended_at)) = &status.ended else {
+        return;
+    };
+    if time.elapsed_seconds() - ended_at < MATCH_ABORTED_SECONDS {
+        return;
+    }
+    info!("leaving the match: {message}");
+    commands.insert_resource(LobbyNotice(message.clone()));
+    status.ended = None;
+    // the connection outlives the session, so it's back to waiting for whoever's still there
+    if *mode == GameMode::Direct {
+        app_state.set(AppState::DirectConnect);
+    } else {
+        app_state.set(AppState::Lobby);
+    }
+}
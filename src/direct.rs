@@ -0,0 +1,586 @@
+//! Direct connect: both players give each other's `ip:port` and play over a single UDP socket,
+//! no matchbox server needed.
+//!
+//! GGRS comes with its own `UdpNonBlockingSocket`, but that one addresses players by
+//! `SocketAddr`, while `GgrsConfig` and everything agreeing on a match around it (`MatchPeers`,
+//! character select, the rematch menu) address them by matchbox `PeerId`. The socket also keeps
+//! carrying hellos next to the GGRS messages, so `DirectChannel` wraps it instead, telling the
+//! two apart by their first byte and standing in the id from the other player's hello for
+//! their address.
+//!
+//! Hellos are encoded with ron like the lobby messages, GGRS messages with bincode like the
+//! matchbox socket sends them.
+
+use crate::{
+    args::Args,
+    configure_session,
+    lobby::{Handshake, LobbyInbox, LobbyMessage, LobbyNotice, MatchPeers, MatchSettings},
+    menu::{
+        despawn_menu, navigate_menu, spawn_menu, GameMode, MenuCursor, MenuMessage, MENU_BACKGROUND,
+    },
+    settings::Settings,
+    stage::{Stage, Stages},
+    AppState, GgrsConfig,
+};
+use bevy::prelude::*;
+use bevy_ggrs::{
+    ggrs::{Message, NonBlockingSocket, P2PSession, PlayerType, SessionState},
+    Session,
+};
+use bevy_matchbox::prelude::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use uuid::Uuid;
+
+/// Seconds between hellos, they're repeated since any of them can get lost
+const HELLO_INTERVAL: f32 = 0.25;
+/// Big enough for any GGRS message or hello
+const RECV_BUFFER_SIZE: usize = 4096;
+/// First byte of every packet, telling hellos and GGRS messages apart on the one socket
+const HELLO_PACKET: u8 = 0;
+const GGRS_PACKET: u8 = 1;
+
+/// Everything the other player needs to know before a direct match. It's state rather than
+/// events, so losing a hello doesn't matter as long as one of the next ones arrives.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Hello {
+    handshake: Handshake,
+    /// Random id standing in for the matchbox one, the lower id is the host like in the lobby
+    id: PeerId,
+    /// Which match the pick and rematch vote are for, hellos of an earlier one are dropped
+    round: u32,
+    stage: String,
+    /// Character and palette locked in on character select
+    pick: Option<(String, usize)>,
+    /// Stage voted for after the last match, and whether characters get picked again
+    rematch: Option<(String, bool)>,
+    /// The sender confirmed the knockout and only waits on the other side to do the same
+    match_over: bool,
+}
+
+/// Just the handshake of a hello, which can still be read when the rest of another build's
+/// hello can't
+#[derive(Deserialize)]
+struct HelloHandshake {
+    handshake: Handshake,
+}
+
+/// Reads a hello, or part of one, from a packet without its tag
+fn decode<T: for<'de> Deserialize<'de>>(packet: &[u8]) -> Option<T> {
+    ron::from_str(std::str::from_utf8(packet).ok()?).ok()
+}
+
+/// UDP socket to the one other player of a direct match, given with `--connect`.
+/// Hellos go out on it until the match starts, then GGRS takes over through a clone of it.
+#[derive(Resource)]
+pub struct DirectSocket {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    id: PeerId,
+    /// Counts up on every reconnect and every finished match, both sides go through the same ones
+    round: u32,
+    /// Latest hello of the other player
+    remote: Option<Hello>,
+    /// Why the other player can't be played, when their handshake doesn't match
+    refused: Option<String>,
+    /// Sent along with the hellos once picked
+    pub pick: Option<(String, usize)>,
+    /// Sent along with the hellos once voted for on the rematch menu
+    pub rematch: Option<(String, bool)>,
+    /// Sent along with the hellos once the knockout is confirmed locally
+    pub match_over: bool,
+    /// Set by the session's channel once the other side confirmed the knockout too
+    peer_match_over: Arc<AtomicBool>,
+    last_hello: f32,
+}
+
+impl DirectSocket {
+    pub fn bind(port: u16, peer: SocketAddr) -> std::io::Result<DirectSocket> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_nonblocking(true)?;
+        Ok(DirectSocket {
+            socket,
+            peer,
+            id: PeerId(Uuid::new_v4()),
+            round: 0,
+            remote: None,
+            refused: None,
+            pick: None,
+            rematch: None,
+            match_over: false,
+            peer_match_over: Arc::default(),
+            last_hello: f32::NEG_INFINITY,
+        })
+    }
+
+    /// Forgets whatever was agreed on for the last match, so it gets agreed on again
+    pub fn next_round(&mut self) {
+        self.round += 1;
+        self.remote = None;
+        self.refused = None;
+        self.pick = None;
+        self.rematch = None;
+        self.match_over = false;
+        self.peer_match_over = Arc::default();
+    }
+
+    /// Whether the other player confirmed the knockout of the match being played
+    pub fn peer_match_over(&self) -> bool {
+        self.peer_match_over.load(Ordering::Relaxed)
+    }
+
+    /// Everything this side knows about the next match so far
+    fn hello(&self, settings: &Settings, stage: String) -> Hello {
+        Hello {
+            handshake: Handshake::new(settings),
+            id: self.id,
+            round: self.round,
+            stage,
+            pick: self.pick.clone(),
+            rematch: self.rematch.clone(),
+            match_over: self.match_over,
+        }
+    }
+
+    fn send_hello(&self, hello: &Hello) {
+        let mut packet = vec![HELLO_PACKET];
+        match ron::to_string(hello) {
+            Ok(encoded) => packet.extend(encoded.into_bytes()),
+            Err(err) => {
+                error!("failed to encode {hello:?}: {err}");
+                return;
+            }
+        }
+        if let Err(err) = self.socket.send_to(&packet, self.peer) {
+            warn!("failed to send hello to {}: {err}", self.peer);
+        }
+    }
+
+    /// Hellos of the round being agreed on that arrived since the last read. A handshake that
+    /// doesn't match `handshake` refuses the other player instead.
+    fn read_hellos(&mut self, handshake: &Handshake) -> Vec<Hello> {
+        let mut hellos = Vec::new();
+        let mut buffer = [0; RECV_BUFFER_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, from)) if from == self.peer && len > 0 && buffer[0] == HELLO_PACKET => {
+                    // another build's hello may not be readable, but its handshake is
+                    let hello = decode::<Hello>(&buffer[1..len]);
+                    let remote_handshake = match &hello {
+                        Some(hello) => Some(hello.handshake.clone()),
+                        None => decode::<HelloHandshake>(&buffer[1..len]).map(|h| h.handshake),
+                    };
+                    if let Some(reason) =
+                        remote_handshake.and_then(|remote| remote.mismatch(handshake))
+                    {
+                        self.refused = Some(reason);
+                        continue;
+                    }
+                    match hello {
+                        // late hellos from before the last match ended would bring back its picks
+                        Some(hello) if hello.round != self.round => {}
+                        Some(hello) => hellos.push(hello),
+                        None => warn!("garbled hello from {from}"),
+                    }
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {}
+            }
+        }
+        hellos
+    }
+
+    /// Hands the socket to GGRS for the match against `peer_id`, the other player
+    fn channel(&self, peer_id: PeerId) -> std::io::Result<DirectChannel> {
+        Ok(DirectChannel {
+            socket: self.socket.try_clone()?,
+            peer: self.peer,
+            peer_id,
+            round: self.round,
+            peer_match_over: self.peer_match_over.clone(),
+        })
+    }
+}
+
+/// The direct socket as GGRS sees it, with the other player's address behind their hello id.
+/// The hellos going around during the match only matter for telling when it's over.
+struct DirectChannel {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    peer_id: PeerId,
+    round: u32,
+    peer_match_over: Arc<AtomicBool>,
+}
+
+impl NonBlockingSocket<PeerId> for DirectChannel {
+    fn send_to(&mut self, msg: &Message, addr: &PeerId) {
+        if *addr != self.peer_id {
+            return;
+        }
+        let mut packet = vec![GGRS_PACKET];
+        match bincode::serialize(msg) {
+            Ok(encoded) => packet.extend(encoded),
+            Err(err) => {
+                error!("failed to encode GGRS message: {err}");
+                return;
+            }
+        }
+        // a packet that can't go out is lost like any other, GGRS sends it again
+        if let Err(err) = self.socket.send_to(&packet, self.peer) {
+            warn!("failed to send to {}: {err}", self.peer);
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        let mut messages = Vec::new();
+        let mut buffer = [0; RECV_BUFFER_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, from)) if from == self.peer && len > 0 && buffer[0] == GGRS_PACKET => {
+                    match bincode::deserialize(&buffer[1..len]) {
+                        Ok(message) => messages.push((self.peer_id, message)),
+                        Err(err) => warn!("garbled GGRS message from {from}: {err}"),
+                    }
+                }
+                Ok((len, from)) if from == self.peer && len > 0 && buffer[0] == HELLO_PACKET => {
+                    // a hello of a later round means they already left the match behind
+                    if let Some(hello) = decode::<Hello>(&buffer[1..len]) {
+                        if hello.round > self.round
+                            || (hello.round == self.round && hello.match_over)
+                        {
+                            self.peer_match_over.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+                // strangers
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                // windows reports the other side going away on the next read, GGRS times them out
+                Err(_) => {}
+            }
+        }
+        messages
+    }
+}
+
+/// Marker component for the direct connect screen
+#[derive(Component)]
+struct DirectConnectUI;
+
+/// Shown under the cancel entry while waiting for the other player
+#[derive(Resource)]
+struct DirectConnectStatus(String);
+
+pub struct DirectConnectPlugin;
+
+impl Plugin for DirectConnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(AppState::DirectConnect), direct_connect_startup)
+            .add_systems(
+                Update,
+                send_hello.run_if(resource_exists::<DirectSocket>().and_then(hellos_needed)),
+            )
+            .add_systems(
+                Update,
+                (receive_hellos, direct_connect_system)
+                    .chain()
+                    .run_if(in_state(AppState::DirectConnect)),
+            )
+            .add_systems(
+                Update,
+                receive_hellos.run_if(
+                    in_state(AppState::CharacterSelect)
+                        .or_else(in_state(AppState::MatchOver))
+                        .and_then(resource_exists::<DirectSocket>()),
+                ),
+            )
+            .add_systems(
+                OnExit(AppState::DirectConnect),
+                despawn_menu::<DirectConnectUI>,
+            );
+    }
+}
+
+fn direct_connect_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    args: Res<Args>,
+    notice: Option<Res<LobbyNotice>>,
+    mut socket: ResMut<DirectSocket>,
+    mut cursor: ResMut<MenuCursor>,
+) {
+    socket.next_round();
+
+    cursor.0 = 0;
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        "Direct Connect",
+        &["Cancel"],
+        MENU_BACKGROUND,
+        DirectConnectUI,
+    );
+    let waiting = format!("Waiting for {} on port {}", socket.peer, args.local_port);
+    let message = match notice {
+        Some(notice) => format!("{}\n{waiting}", notice.0),
+        None => waiting,
+    };
+    commands.insert_resource(DirectConnectStatus(message));
+    commands.remove_resource::<LobbyNotice>();
+}
+
+/// Hellos only go out while agreeing on the next match, and in the match until the other side is
+/// in it too, as they may still be waiting on the last pick or vote, or once it's over. Anywhere
+/// else nobody would read them and they'd pile up for later.
+fn hellos_needed(
+    state: Res<State<AppState>>,
+    socket: Res<DirectSocket>,
+    session: Option<Res<Session<GgrsConfig>>>,
+) -> bool {
+    match state.get() {
+        AppState::DirectConnect | AppState::CharacterSelect | AppState::MatchOver => true,
+        AppState::InGame => match session.as_deref() {
+            Some(Session::P2P(session)) => {
+                session.current_state() != SessionState::Running || socket.match_over
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn send_hello(
+    time: Res<Time>,
+    args: Res<Args>,
+    stages: Res<Stages>,
+    settings: Res<Settings>,
+    mut socket: ResMut<DirectSocket>,
+) {
+    let now = time.elapsed_seconds();
+    if now - socket.last_hello < HELLO_INTERVAL {
+        return;
+    }
+    socket.last_hello = now;
+
+    let stage = stages.0[stages.index_or_default(args.stage.as_deref())]
+        .name
+        .clone();
+    socket.send_hello(&socket.hello(&settings, stage));
+}
+
+/// Keeps the other player's latest hello, and hands their pick to character select
+/// and their vote to the rematch menu
+fn receive_hellos(
+    state: Res<State<AppState>>,
+    settings: Res<Settings>,
+    mut socket: ResMut<DirectSocket>,
+    mut inbox: ResMut<LobbyInbox>,
+) {
+    let handshake = Handshake::new(&settings);
+    for hello in socket.read_hellos(&handshake) {
+        if socket.remote.is_none() {
+            info!("heard from {} ({})", socket.peer, hello.id);
+        }
+        match (state.get(), &hello.pick, &hello.rematch) {
+            (AppState::CharacterSelect, Some((character, palette)), _) => {
+                let pick = LobbyMessage::CharacterPick {
+                    character: character.clone(),
+                    palette: *palette,
+                };
+                inbox.0.push((hello.id, pick));
+            }
+            (AppState::MatchOver, _, Some((stage, reselect))) => {
+                let vote = LobbyMessage::Rematch {
+                    stage: stage.clone(),
+                    reselect: *reselect,
+                };
+                inbox.0.push((hello.id, vote));
+            }
+            _ => {}
+        }
+        socket.remote = Some(hello);
+    }
+}
+
+/// Moves on to character select once the other player answered, agreeing on the match like the lobby does
+#[allow(clippy::too_many_arguments)]
+fn direct_connect_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    args: Res<Args>,
+    stages: Res<Stages>,
+    settings: Res<Settings>,
+    socket: Res<DirectSocket>,
+    mut status: ResMut<DirectConnectStatus>,
+    mut cursor: ResMut<MenuCursor>,
+    mut text: Query<&mut Text, With<MenuMessage>>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    if let Ok(mut text) = text.get_single_mut() {
+        text.sections[0].value = status.0.clone();
+    }
+    if navigate_menu(&keys, &mut cursor, 1) || keys.just_pressed(KeyCode::Escape) {
+        app_state.set(AppState::MainMenu);
+        return;
+    }
+
+    if let Some(reason) = &socket.refused {
+        let refusal = format!("Can't play the other player, {reason}");
+        refuse(&mut status, socket.peer, refusal);
+        return;
+    }
+    let Some(remote) = &socket.remote else {
+        return;
+    };
+    // the host's settings and stage are played with, the same as in the lobby
+    let (host_settings, stage) = if socket.id < remote.id {
+        (
+            *settings,
+            Some(stages.index_or_default(args.stage.as_deref())),
+        )
+    } else {
+        let stage = stages.0.iter().position(|stage| stage.name == remote.stage);
+        (remote.handshake.settings, stage)
+    };
+    // falling back to another stage would have each side play on a different one
+    let Some(stage) = stage else {
+        let refusal = format!(
+            "The other player picked {}, which isn't in your game",
+            remote.stage
+        );
+        refuse(&mut status, socket.peer, refusal);
+        return;
+    };
+
+    let mut players = vec![socket.id, remote.id];
+    players.sort();
+    commands.insert_resource(Stage(stages.0[stage].clone()));
+    commands.insert_resource(MatchSettings(settings.negotiated(&host_settings)));
+    commands.insert_resource(MatchPeers {
+        players,
+        spectators: Vec::new(),
+        local: socket.id,
+    });
+    app_state.set(AppState::CharacterSelect);
+}
+
+/// Shows why the other player can't be played. Hellos keep coming in, so it's only logged once.
+fn refuse(status: &mut DirectConnectStatus, peer: SocketAddr, refusal: String) {
+    if status.0 != refusal {
+        warn!("can't play {peer}: {refusal}");
+        status.0 = refusal;
+    }
+}
+
+/// Starts the GGRS session on the direct socket. Anything going wrong on the way goes back
+/// to waiting for the other player, saying what happened.
+pub fn start_direct_session(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    args: Res<Args>,
+    settings: Res<MatchSettings>,
+    peers: Res<MatchPeers>,
+    socket: Res<DirectSocket>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    let players = mode.players(&args);
+    match direct_session(&mut commands, players, &settings.0, &peers, &socket) {
+        Ok(session) => commands.insert_resource(Session::P2P(session)),
+        Err(err) => {
+            warn!("failed to start the direct match: {err}");
+            commands.insert_resource(LobbyNotice(format!("Couldn't start the match, {err}")));
+            app_state.set(AppState::DirectConnect);
+        }
+    }
+}
+
+fn direct_session(
+    commands: &mut Commands,
+    players: usize,
+    settings: &Settings,
+    peers: &MatchPeers,
+    socket: &DirectSocket,
+) -> Result<P2PSession<GgrsConfig>, String> {
+    let mut session_builder = configure_session(commands, players, settings);
+    let mut peer_id = None;
+    for (i, &player) in peers.players.iter().enumerate() {
+        let player = if player == peers.local {
+            PlayerType::Local
+        } else {
+            peer_id = Some(player);
+            PlayerType::Remote(player)
+        };
+        session_builder = session_builder
+            .add_player(player, i)
+            .map_err(|err| err.to_string())?;
+    }
+
+    let peer_id = peer_id.ok_or("the other player isn't in it")?;
+    let channel = socket
+        .channel(peer_id)
+        .map_err(|err| format!("the socket can't be shared with GGRS: {err}"))?;
+    session_builder
+        .start_p2p_session(channel)
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    /// Two direct sockets on 127.0.0.1, connected to each other
+    fn loopback() -> (DirectSocket, DirectSocket) {
+        let unset = SocketAddr::from(([127, 0, 0, 1], 0));
+        let mut a = DirectSocket::bind(0, unset).unwrap();
+        let mut b = DirectSocket::bind(0, unset).unwrap();
+        let address = |socket: &DirectSocket| {
+            let port = socket.socket.local_addr().unwrap().port();
+            SocketAddr::from(([127, 0, 0, 1], port))
+        };
+        a.peer = address(&b);
+        b.peer = address(&a);
+        (a, b)
+    }
+
+    /// Reads until something arrived, even loopback takes a moment
+    fn wait_for<T>(mut read: impl FnMut() -> Vec<T>) -> Vec<T> {
+        for _ in 0..100 {
+            let received = read();
+            if !received.is_empty() {
+                return received;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Vec::new()
+    }
+
+    #[test]
+    fn hello_and_ggrs_message_go_through_loopback() {
+        let (a, mut b) = loopback();
+        let settings = Settings::from_args(&Args::default()).unwrap();
+
+        a.send_hello(&a.hello(&settings, "Dojo".to_string()));
+        let hellos = wait_for(|| b.read_hellos(&Handshake::new(&settings)));
+        assert_eq!(hellos.len(), 1);
+        assert_eq!(hellos[0].id, a.id);
+        assert_eq!(hellos[0].stage, "Dojo");
+        assert!(b.refused.is_none());
+
+        let mut a_channel = a.channel(b.id).unwrap();
+        let mut b_channel = b.channel(a.id).unwrap();
+        // GGRS keeps what's in its messages to itself, these bytes are a keep alive
+        let message: Message = bincode::deserialize(&[0, 0, 7, 0, 0, 0]).unwrap();
+        a_channel.send_to(&message, &b.id);
+        let messages = wait_for(|| b_channel.receive_all_messages());
+        assert_eq!(messages, vec![(a.id, message)]);
+    }
+}
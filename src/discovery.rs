@@ -0,0 +1,309 @@
+use crate::{
+    args::Args,
+    menu::{
+        despawn_menu, highlight_menu_entries, navigate_menu, spawn_menu, GameMode, MenuCursor,
+        MenuMessage, MENU_BACKGROUND,
+    },
+    room::Room,
+    AppState,
+};
+use bevy::prelude::*;
+use bevy_matchbox::{matchbox_signaling::SignalingServer, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+};
+
+/// Port hosts broadcast their beacons to, and the LAN browser listens on
+const DISCOVERY_PORT: u16 = 7070;
+/// Seconds between beacons of a host
+const BEACON_INTERVAL: f32 = 1.0;
+/// Seconds without a beacon before a host drops off the list
+const HOST_TIMEOUT: f32 = 3.0;
+/// Marks beacons of this game, anything else broadcast on the port is ignored
+const BEACON_GAME: &str = "bevy_fighter";
+
+/// Broadcast by a LAN host while it waits for someone to join
+#[derive(Serialize, Deserialize, Debug)]
+struct Beacon {
+    game: String,
+    name: String,
+    /// Port the host's signaling server is on, the address is wherever the beacon came from
+    port: u16,
+}
+
+/// A game hosted on the LAN. The host runs the signaling server everyone's lobby connects
+/// through, so nobody needs a matchbox server, and tells the network about it while in the lobby.
+#[derive(Resource)]
+pub struct LanHost {
+    /// Keeps running for as long as the host is around
+    _server: MatchboxServer,
+    beacon_socket: UdpSocket,
+    port: u16,
+    last_beacon: f32,
+}
+
+impl LanHost {
+    fn start(port: u16) -> std::io::Result<LanHost> {
+        // the server only finds out its port is taken from its own task, so it's checked up front
+        drop(TcpListener::bind(("0.0.0.0", port))?);
+        let beacon_socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        beacon_socket.set_broadcast(true)?;
+        let server = SignalingServer::full_mesh_builder((Ipv4Addr::UNSPECIFIED, port)).build();
+        Ok(LanHost {
+            _server: MatchboxServer::from(server),
+            beacon_socket,
+            port,
+            last_beacon: f32::NEG_INFINITY,
+        })
+    }
+}
+
+struct LanGame {
+    addr: SocketAddr,
+    name: String,
+    last_seen: f32,
+}
+
+/// Hosts heard from while the LAN browser is open
+#[derive(Resource)]
+struct LanBrowser {
+    /// `None` when the discovery port is taken, like by another browser on the same machine
+    socket: Option<UdpSocket>,
+    hosts: Vec<LanGame>,
+    /// Whether picking a host joins its lobby to watch rather than play
+    spectate: bool,
+    /// Entries the menu was last spawned with
+    entries: Vec<String>,
+    message: String,
+}
+
+/// Marker component for LAN browser UI
+#[derive(Component)]
+struct LanBrowserUI;
+
+pub struct DiscoveryPlugin;
+
+impl Plugin for DiscoveryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(AppState::LanBrowser), lan_browser_startup)
+            .add_systems(
+                Update,
+                (receive_beacons, lan_browser_input, highlight_menu_entries)
+                    .chain()
+                    .run_if(in_state(AppState::LanBrowser)),
+            )
+            .add_systems(
+                Update,
+                // joining after the players ready up is too late, even to watch
+                send_beacon
+                    .run_if(in_state(AppState::Lobby).and_then(resource_exists::<LanHost>())),
+            )
+            .add_systems(
+                OnExit(AppState::LanBrowser),
+                (despawn_menu::<LanBrowserUI>, close_lan_browser),
+            );
+    }
+}
+
+fn lan_browser_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut cursor: ResMut<MenuCursor>,
+) {
+    let socket = UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT))
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket));
+    let (socket, message) = match socket {
+        Ok(socket) => (
+            Some(socket),
+            "Looking for games on the local network".to_string(),
+        ),
+        Err(err) => {
+            warn!("can't listen for LAN games: {err}");
+            (
+                None,
+                format!("Can't look for games, port {DISCOVERY_PORT} is in use: {err}"),
+            )
+        }
+    };
+    let browser = LanBrowser {
+        socket,
+        hosts: Vec::new(),
+        spectate: false,
+        entries: lan_entries(&[], false),
+        message,
+    };
+
+    cursor.0 = 0;
+    spawn_lan_browser(&mut commands, &asset_server, &browser.entries);
+    commands.insert_resource(browser);
+}
+
+fn lan_entries(hosts: &[LanGame], spectate: bool) -> Vec<String> {
+    let mut entries = vec!["Host a game".to_string()];
+    for host in hosts {
+        entries.push(format!("{} ({})", host.name, host.addr.ip()));
+    }
+    let role = if spectate { "Spectator" } else { "Player" };
+    entries.push(format!("Join as: {role}"));
+    entries.push("Back".to_string());
+    entries
+}
+
+fn spawn_lan_browser(commands: &mut Commands, asset_server: &AssetServer, entries: &[String]) {
+    let entries: Vec<&str> = entries.iter().map(String::as_str).collect();
+    spawn_menu(
+        commands,
+        asset_server,
+        "LAN Games",
+        &entries,
+        MENU_BACKGROUND,
+        LanBrowserUI,
+    );
+}
+
+/// Keeps the host list up to date, spawning the menu again whenever it changes
+fn receive_beacons(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    mut browser: ResMut<LanBrowser>,
+    mut cursor: ResMut<MenuCursor>,
+    ui: Query<Entity, With<LanBrowserUI>>,
+) {
+    let now = time.elapsed_seconds();
+    let browser = &mut *browser;
+    if let Some(socket) = &browser.socket {
+        let mut buffer = [0; 512];
+        loop {
+            match socket.recv_from(&mut buffer) {
+                Ok((len, from)) => {
+                    let beacon = std::str::from_utf8(&buffer[..len])
+                        .ok()
+                        .and_then(|text| ron::from_str::<Beacon>(text).ok());
+                    let Some(beacon) = beacon else {
+                        continue;
+                    };
+                    if beacon.game != BEACON_GAME {
+                        continue;
+                    }
+                    let addr = SocketAddr::new(from.ip(), beacon.port);
+                    browser.hosts.retain(|host| host.addr != addr);
+                    browser.hosts.push(LanGame {
+                        addr,
+                        name: beacon.name,
+                        last_seen: now,
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {}
+            }
+        }
+    }
+    browser
+        .hosts
+        .retain(|host| now - host.last_seen < HOST_TIMEOUT);
+    browser.hosts.sort_by_key(|host| host.addr);
+
+    let entries = lan_entries(&browser.hosts, browser.spectate);
+    if entries == browser.entries {
+        return;
+    }
+    for entity in &ui {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_lan_browser(&mut commands, &asset_server, &entries);
+    cursor.0 = cursor.0.min(entries.len() - 1);
+    browser.entries = entries;
+}
+
+/// Hosting or joining both go on to the lobby, the same as a matchbox room
+#[allow(clippy::too_many_arguments)]
+fn lan_browser_input(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    args: Res<Args>,
+    mut browser: ResMut<LanBrowser>,
+    mut cursor: ResMut<MenuCursor>,
+    mut mode: ResMut<GameMode>,
+    mut message: Query<&mut Text, With<MenuMessage>>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    if let Ok(mut text) = message.get_single_mut() {
+        text.sections[0].value = browser.message.clone();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        app_state.set(AppState::MainMenu);
+        return;
+    }
+    if !navigate_menu(&keys, &mut cursor, browser.entries.len()) {
+        return;
+    }
+
+    let back = browser.entries.len() - 1;
+    match cursor.0 {
+        0 => match LanHost::start(args.local_port) {
+            Ok(host) => {
+                info!("hosting a LAN game on port {}", args.local_port);
+                let server = SocketAddr::from((Ipv4Addr::LOCALHOST, args.local_port));
+                commands.insert_resource(Room::Lan(server));
+                commands.insert_resource(host);
+                *mode = GameMode::Online;
+                app_state.set(AppState::Lobby);
+            }
+            Err(err) => {
+                warn!("can't host a LAN game: {err}");
+                browser.message = format!("Can't host on port {}: {err}", args.local_port);
+            }
+        },
+        index if index == back => app_state.set(AppState::MainMenu),
+        // the menu gets spawned again with the new role once the entries change
+        index if index == back - 1 => browser.spectate = !browser.spectate,
+        index => {
+            let host = &browser.hosts[index - 1];
+            info!("joining the LAN game of {} at {}", host.name, host.addr);
+            commands.insert_resource(Room::Lan(host.addr));
+            *mode = if browser.spectate {
+                GameMode::Spectate
+            } else {
+                GameMode::Online
+            };
+            app_state.set(AppState::Lobby);
+        }
+    }
+}
+
+fn close_lan_browser(mut commands: Commands) {
+    // frees the discovery port for other instances
+    commands.remove_resource::<LanBrowser>();
+}
+
+/// Tells the local network about a hosted game while its lobby is open
+fn send_beacon(time: Res<Time>, args: Res<Args>, mut host: ResMut<LanHost>) {
+    let now = time.elapsed_seconds();
+    if now - host.last_beacon < BEACON_INTERVAL {
+        return;
+    }
+    host.last_beacon = now;
+
+    let beacon = Beacon {
+        game: BEACON_GAME.to_string(),
+        name: args.name.clone().unwrap_or_else(|| "Player".to_string()),
+        port: host.port,
+    };
+    // encoded like the lobby messages
+    let packet = match ron::to_string(&beacon) {
+        Ok(packet) => packet,
+        Err(err) => {
+            error!("failed to encode {beacon:?}: {err}");
+            return;
+        }
+    };
+    if let Err(err) = host
+        .beacon_socket
+        .send_to(packet.as_bytes(), (Ipv4Addr::BROADCAST, DISCOVERY_PORT))
+    {
+        warn!("failed to broadcast the hosted game: {err}");
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    combat::{Health, MAX_HEALTH},
+    in_match,
+    meter::{SuperMeter, MAX_METER},
+    AppState, Player, PLAYERS,
+};
+use bevy::prelude::*;
+
+const BAR_WIDTH: f32 = 40.0;
+const HEALTH_BAR_HEIGHT: f32 = 24.0;
+const METER_BAR_HEIGHT: f32 = 12.0;
+const HEALTH_COLOR: Color = Color::rgb(0.9, 0.8, 0.1);
+const METER_COLOR: Color = Color::rgb(0.2, 0.6, 1.0);
+/// Meter turns this color once there's enough for a super
+const METER_FULL_COLOR: Color = Color::rgb(1.0, 0.85, 0.2);
+
+/// Marker component for the HUD root
+#[derive(Component)]
+struct HudUI;
+
+/// Fill of the health bar belonging to the player with this handle
+#[derive(Component)]
+struct HealthBar(usize);
+
+/// Fill of the meter bar belonging to the player with this handle
+#[derive(Component)]
+struct MeterBar(usize);
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        // the HUD stays up while paused, it's only removed once the match is left
+        app.add_systems(
+            OnEnter(AppState::InGame),
+            hud_startup.run_if(not(any_with_component::<HudUI>())),
+        )
+        .add_systems(Update, update_hud.run_if(in_match))
+        .add_systems(OnEnter(AppState::MainMenu), hud_cleanup)
+        .add_systems(OnEnter(AppState::Lobby), hud_cleanup)
+        .add_systems(OnEnter(AppState::DirectConnect), hud_cleanup)
+        .add_systems(OnEnter(AppState::CharacterSelect), hud_cleanup);
+    }
+}
+
+fn hud_startup(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::SpaceBetween,
+                padding: UiRect::all(Val::Px(16.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for handle in 0..PLAYERS {
+                // player 2's bars sit on the right edge so both drain away from the middle
+                let justify = if handle == 0 {
+                    JustifyContent::FlexStart
+                } else {
+                    JustifyContent::FlexEnd
+                };
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            width: Val::Percent(BAR_WIDTH),
+                            flex_direction: FlexDirection::Column,
+                            row_gap: Val::Px(6.0),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        let health = HealthBar(handle);
+                        spawn_bar(parent, HEALTH_BAR_HEIGHT, HEALTH_COLOR, justify, health);
+                        let meter = MeterBar(handle);
+                        spawn_bar(parent, METER_BAR_HEIGHT, METER_COLOR, justify, meter);
+                    });
+            }
+        })
+        .insert(HudUI);
+}
+
+/// Spawns a bar background with a fill tagged with `marker` inside it,
+/// `justify` decides which side the fill shrinks towards
+fn spawn_bar(
+    parent: &mut ChildBuilder,
+    height: f32,
+    color: Color,
+    justify: JustifyContent,
+    marker: impl Bundle,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Px(height),
+                justify_content: justify,
+                ..default()
+            },
+            background_color: Color::rgb(0.2, 0.05, 0.05).into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    background_color: color.into(),
+                    ..default()
+                },
+                marker,
+            ));
+        });
+}
+
+fn update_hud(
+    meter: Res<SuperMeter>,
+    players: Query<(&Player, &Health)>,
+    mut health_bars: Query<(&HealthBar, &mut Style), Without<MeterBar>>,
+    mut meter_bars: Query<(&MeterBar, &mut Style, &mut BackgroundColor), Without<HealthBar>>,
+) {
+    for (bar, mut style) in &mut health_bars {
+        if let Some((_, health)) = players.iter().find(|(player, _)| player.handle == bar.0) {
+            style.width = Val::Percent(100.0 * health.0 as f32 / MAX_HEALTH as f32);
+        }
+    }
+    for (bar, mut style, mut color) in &mut meter_bars {
+        let amount = meter.get(bar.0);
+        style.width = Val::Percent(100.0 * amount as f32 / MAX_METER as f32);
+        color.0 = if amount >= MAX_METER {
+            METER_FULL_COLOR
+        } else {
+            METER_COLOR
+        };
+    }
+}
+
+fn hud_cleanup(query: Query<Entity, With<HudUI>>, mut commands: Commands) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,9 +1,10 @@
 use bevy::core::{Pod, Zeroable};
 use bevy::prelude::*;
 use bevy::utils::HashMap;
-use bevy_ggrs::{LocalInputs, LocalPlayers};
+use bevy_ggrs::{LocalInputs, LocalPlayers, PlayerInputs};
+use serde::Deserialize;
 
-use crate::GgrsConfig;
+use crate::{replay::ReplayPlayback, AppState, FrameCount, GgrsConfig, Player};
 
 #[repr(C)]
 #[derive(Copy, Clone, PartialEq, Pod, Zeroable, Debug, Default, Reflect)]
@@ -31,71 +32,220 @@ pub const INPUT_SLASH_JUST_PRESSED: u16 = 1 << 13;
 pub const INPUT_HEAVY: u16 = 1 << 14;
 pub const INPUT_HEAVY_JUST_PRESSED: u16 = 1 << 15;
 
-pub fn input(
-    mut commands: Commands,
-    keyboard: Res<Input<KeyCode>>,
-    local_players: Res<LocalPlayers>,
-) {
-    let mut local_inputs = HashMap::new();
+/// How many frames of input each fighter remembers
+pub const INPUT_HISTORY_LEN: usize = 30;
 
-    let mut input: u16 = 0;
+/// Ring buffer of the last few frames of a player's buttons, part of the rollback state
+/// so anything reading it (dashes, motion inputs) gets decided the same on both peers.
+#[derive(Component, Clone, Copy, Debug, Hash, Reflect)]
+#[reflect(Component, Hash)]
+pub struct InputHistory {
+    buttons: [u16; INPUT_HISTORY_LEN],
+    head: usize,
+}
 
-    // Direction Inputs
-    if keyboard.pressed(KeyCode::W) {
-        if keyboard.just_pressed(KeyCode::W) {
-            input |= INPUT_UP_JUST_PRESSED;
+impl Default for InputHistory {
+    fn default() -> Self {
+        InputHistory {
+            buttons: [0; INPUT_HISTORY_LEN],
+            head: 0,
         }
-        input |= INPUT_UP;
     }
-    if keyboard.pressed(KeyCode::A) {
-        if keyboard.just_pressed(KeyCode::A) {
-            input |= INPUT_LEFT_JUST_PRESSED;
-        }
-        input |= INPUT_LEFT;
+}
+
+impl InputHistory {
+    pub fn push(&mut self, buttons: u16) {
+        self.head = (self.head + 1) % INPUT_HISTORY_LEN;
+        self.buttons[self.head] = buttons;
     }
-    if keyboard.pressed(KeyCode::S) {
-        if keyboard.just_pressed(KeyCode::S) {
-            input |= INPUT_DOWN_JUST_PRESSED;
-        }
-        input |= INPUT_DOWN;
+
+    /// Buttons from `frames_ago` frames back, 0 being the current frame
+    pub fn get(&self, frames_ago: usize) -> u16 {
+        let frames_ago = frames_ago.min(INPUT_HISTORY_LEN - 1);
+        self.buttons[(self.head + INPUT_HISTORY_LEN - frames_ago) % INPUT_HISTORY_LEN]
     }
-    if keyboard.pressed(KeyCode::D) {
-        if keyboard.just_pressed(KeyCode::D) {
-            input |= INPUT_RIGHT_JUST_PRESSED;
-        }
-        input |= INPUT_RIGHT;
+
+    /// Whether any of the `mask` bits were set in the frames `from..=to` ago
+    pub fn any_within(&self, mask: u16, from: usize, to: usize) -> bool {
+        (from..=to).any(|frames_ago| self.get(frames_ago) & mask != 0)
     }
+}
+
+/// How many frames back a motion input can start
+pub const MOTION_WINDOW: usize = 15;
+/// How many frames ago the last direction of a motion can have been hit and still count
+const MOTION_LENIENCY: usize = 4;
 
-    // Attack inputs
-    if keyboard.pressed(KeyCode::U) {
-        if keyboard.just_pressed(KeyCode::U) {
-            input |= INPUT_PUNCH_JUST_PRESSED;
+/// Gets the held direction in numpad notation (6 is forward, 2 is down, 5 is neutral)
+pub fn numpad_direction(buttons: u16, facing_left: bool) -> u8 {
+    let (forward, back) = if facing_left {
+        (INPUT_LEFT, INPUT_RIGHT)
+    } else {
+        (INPUT_RIGHT, INPUT_LEFT)
+    };
+    let horizontal = match (buttons & back != 0, buttons & forward != 0) {
+        (true, false) => 0,
+        (false, true) => 2,
+        _ => 1,
+    };
+    let vertical = match (buttons & INPUT_DOWN != 0, buttons & INPUT_UP != 0) {
+        (true, false) => 0,
+        (false, true) => 6,
+        _ => 3,
+    };
+    1 + horizontal + vertical
+}
+
+/// Directional motions special moves can require before their button
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Motion {
+    /// Down, down-forward, forward
+    QuarterCircleForward,
+    /// Down, down-back, back
+    QuarterCircleBack,
+    /// Forward, down, down-forward
+    DragonPunch,
+    /// Two quarter circle forwards in a row
+    DoubleQuarterCircleForward,
+}
+
+impl Motion {
+    fn sequence(self) -> &'static [u8] {
+        match self {
+            Motion::QuarterCircleForward => &[2, 3, 6],
+            Motion::QuarterCircleBack => &[2, 1, 4],
+            Motion::DragonPunch => &[6, 2, 3],
+            Motion::DoubleQuarterCircleForward => &[2, 3, 6, 2, 3, 6],
         }
-        input |= INPUT_PUNCH;
     }
-    if keyboard.pressed(KeyCode::I) {
-        if keyboard.just_pressed(KeyCode::I) {
-            input |= INPUT_KICK_JUST_PRESSED;
+
+    /// Looks back through the history for the motion's directions in order
+    pub fn performed(self, history: &InputHistory, facing_left: bool) -> bool {
+        let sequence = self.sequence();
+        // double motions get twice as long to be put in
+        let window = MOTION_WINDOW * sequence.len().div_ceil(3);
+        let mut remaining = sequence.len();
+
+        for frames_ago in 0..window.min(INPUT_HISTORY_LEN) {
+            let direction = numpad_direction(history.get(frames_ago), facing_left);
+            if direction == sequence[remaining - 1] {
+                remaining -= 1;
+                if remaining == 0 {
+                    return true;
+                }
+            } else if remaining == sequence.len() && frames_ago >= MOTION_LENIENCY {
+                return false;
+            }
         }
-        input |= INPUT_KICK;
+        false
     }
-    if keyboard.pressed(KeyCode::O) {
-        if keyboard.just_pressed(KeyCode::O) {
-            input |= INPUT_SLASH_JUST_PRESSED;
-        }
-        input |= INPUT_SLASH;
+}
+
+/// Stores this frame's inputs before anything else in the rollback schedule looks at them
+pub fn record_input_history(
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut query: Query<(&Player, &mut InputHistory)>,
+) {
+    for (player, mut history) in &mut query {
+        let buttons = inputs.get(player.handle).map_or(0, |input| input.0.buttons);
+        history.push(buttons);
     }
-    if keyboard.pressed(KeyCode::P) {
-        if keyboard.just_pressed(KeyCode::P) {
-            input |= INPUT_HEAVY_JUST_PRESSED;
+}
+
+/// Throws are done by pressing punch and kick together
+pub fn throw_pressed(buttons: u16) -> bool {
+    let both_held = buttons & INPUT_PUNCH != 0 && buttons & INPUT_KICK != 0;
+    let just_pressed = buttons & (INPUT_PUNCH_JUST_PRESSED | INPUT_KICK_JUST_PRESSED) != 0;
+    both_held && just_pressed
+}
+
+/// Keys one player on this keyboard plays with
+pub struct KeyBindings {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub punch: KeyCode,
+    pub kick: KeyCode,
+    pub slash: KeyCode,
+    pub heavy: KeyCode,
+}
+
+/// Keys of the first local player, and the only one when playing online
+pub const PLAYER_ONE_KEYS: KeyBindings = KeyBindings {
+    up: KeyCode::W,
+    down: KeyCode::S,
+    left: KeyCode::A,
+    right: KeyCode::D,
+    punch: KeyCode::U,
+    kick: KeyCode::I,
+    slash: KeyCode::O,
+    heavy: KeyCode::P,
+};
+
+/// Keys of the second player in local versus
+pub const PLAYER_TWO_KEYS: KeyBindings = KeyBindings {
+    up: KeyCode::Up,
+    down: KeyCode::Down,
+    left: KeyCode::Left,
+    right: KeyCode::Right,
+    punch: KeyCode::Numpad1,
+    kick: KeyCode::Numpad2,
+    slash: KeyCode::Numpad3,
+    heavy: KeyCode::Numpad4,
+};
+
+impl KeyBindings {
+    /// Packs the state of these keys into a player's buttons
+    pub fn read(&self, keyboard: &Input<KeyCode>) -> u16 {
+        let buttons = [
+            // Direction inputs
+            (self.up, INPUT_UP, INPUT_UP_JUST_PRESSED),
+            (self.left, INPUT_LEFT, INPUT_LEFT_JUST_PRESSED),
+            (self.down, INPUT_DOWN, INPUT_DOWN_JUST_PRESSED),
+            (self.right, INPUT_RIGHT, INPUT_RIGHT_JUST_PRESSED),
+            // Attack inputs
+            (self.punch, INPUT_PUNCH, INPUT_PUNCH_JUST_PRESSED),
+            (self.kick, INPUT_KICK, INPUT_KICK_JUST_PRESSED),
+            (self.slash, INPUT_SLASH, INPUT_SLASH_JUST_PRESSED),
+            (self.heavy, INPUT_HEAVY, INPUT_HEAVY_JUST_PRESSED),
+        ];
+
+        let mut input: u16 = 0;
+        for (key, held, just_pressed) in buttons {
+            if keyboard.pressed(key) {
+                if keyboard.just_pressed(key) {
+                    input |= just_pressed;
+                }
+                input |= held;
+            }
         }
-        input |= INPUT_HEAVY;
+        input
     }
+}
 
-    let inferi_input = InferiInput { buttons: input };
+/// Reads the buttons of every local player, each from their own keys.
+/// While watching a replay the recorded buttons are played instead.
+pub fn input(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+    local_players: Res<LocalPlayers>,
+    frame_count: Res<FrameCount>,
+    playback: Option<Res<ReplayPlayback>>,
+) {
+    let mut local_inputs = HashMap::new();
 
-    for handle in &local_players.0 {
-        local_inputs.insert(*handle, inferi_input);
+    for (i, handle) in local_players.0.iter().enumerate() {
+        let buttons = match &playback {
+            Some(playback) => playback.0.buttons(**frame_count, *handle),
+            // the match keeps going under the pause menu online, but the keys are for the menu
+            None if *state.get() == AppState::Paused => 0,
+            None => [PLAYER_ONE_KEYS, PLAYER_TWO_KEYS]
+                .get(i)
+                .map_or(0, |keys| keys.read(&keyboard)),
+        };
+        local_inputs.insert(*handle, InferiInput { buttons });
     }
 
     commands.insert_resource(LocalInputs::<GgrsConfig>(local_inputs));
@@ -1,11 +1,268 @@
-use crate::{args::Args, configure_session, AppState};
+use crate::{
+    args::Args,
+    character::CHARACTER_FILES,
+    configure_session,
+    menu::GameMode,
+    room::{ctrl_pressed, format_code, Clipboard, Room},
+    settings::Settings,
+    stage::{Stage, Stages, STAGE_FILES},
+    AppState,
+};
 use bevy::prelude::*;
-use bevy_ggrs::{ggrs::DesyncDetection, Session};
-use bevy_matchbox::prelude::*;
+use bevy_ggrs::{
+    ggrs::{Message, NonBlockingSocket, PlayerType},
+    Session,
+};
+use bevy_matchbox::{matchbox_socket::WebRtcChannel, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// Unreliable channel the GGRS session runs on
+const GGRS_CHANNEL: usize = 0;
+/// Reliable channel for everything agreed on in the lobby before the session starts
+const LOBBY_CHANNEL: usize = 1;
+
+/// Longest nickname that can be typed in
+const MAX_NICKNAME_LEN: usize = 16;
+
+/// Messages peers exchange over the lobby channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum LobbyMessage {
+    /// Name the sender wants to be shown as
+    Nickname(String),
+    /// Whether the sender is ready, along with the stage they vote for when they are
+    Ready(Option<String>),
+    /// Build, gameplay data and session settings of the sender, sent before anything else
+    Handshake(Handshake),
+    /// The sender only watches, so they don't play or vote on the stage
+    Spectating,
+    /// Sent by the host once everyone is ready, every peer moves on to character select on `stage`
+    Start { stage: String },
+    /// Character and palette the sender locked in on character select
+    CharacterPick { character: String, palette: usize },
+    /// The sender confirmed the knockout, and keeps the session up until every player did
+    MatchOver,
+    /// The sender wants another match after this one, on `stage` and maybe with new picks
+    Rematch { stage: String, reselect: bool },
+}
+
+impl LobbyMessage {
+    pub fn send(&self, socket: &mut MatchboxSocket<MultipleChannels>, peer: PeerId) {
+        let packet = match ron::to_string(self) {
+            Ok(packet) => packet.into_bytes().into_boxed_slice(),
+            Err(err) => {
+                error!("failed to encode {self:?}: {err}");
+                return;
+            }
+        };
+        socket.channel(LOBBY_CHANNEL).send(packet, peer);
+    }
+
+    /// Sends the message to every connected peer
+    pub fn broadcast(&self, socket: &mut MatchboxSocket<MultipleChannels>) {
+        let peers: Vec<PeerId> = socket.connected_peers().collect();
+        for peer in peers {
+            self.send(socket, peer);
+        }
+    }
+}
+
+/// What peers compare before playing together. Different builds or character and stage data
+/// would desync on the first frame, so peers that don't match are refused in the lobby.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Handshake {
+    /// Version and commit the build was made from, see `build.rs`
+    build: String,
+    /// Hash of the character and stage files baked into the build
+    content: u64,
+    pub settings: Settings,
+}
+
+impl Handshake {
+    pub fn new(settings: &Settings) -> Self {
+        Handshake {
+            build: env!("BUILD_ID").to_string(),
+            content: content_hash(),
+            settings: *settings,
+        }
+    }
+
+    /// Why the sender of `self` can't play with the local side, if they can't
+    pub fn mismatch(&self, local: &Handshake) -> Option<String> {
+        if self.build != local.build {
+            Some(format!(
+                "they run build {} and you run {}",
+                self.build, local.build
+            ))
+        } else if self.content != local.content {
+            Some("their characters or stages differ from yours".to_string())
+        } else {
+            self.settings
+                .validate()
+                .err()
+                .map(|err| format!("their settings are invalid: {err}"))
+        }
+    }
+}
+
+/// FNV-1a over the names and contents of the data files, std's hasher isn't guaranteed to
+/// hash the same way on every build. Carriage returns are skipped so a checkout with windows
+/// line endings still matches.
+fn content_hash() -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (name, contents) in CHARACTER_FILES.iter().chain(STAGE_FILES) {
+        for byte in name.bytes().chain(contents.bytes()).filter(|b| *b != b'\r') {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Lobby messages received but not handled yet. Whichever screen is open takes out
+/// the messages it cares about and leaves the rest, so a message that arrives early
+/// (like a character pick while still agreeing on the stage) isn't lost.
+#[derive(Resource, Default)]
+pub struct LobbyInbox(pub Vec<(PeerId, LobbyMessage)>);
+
+/// Who plays and who watches, settled on leaving the lobby
+#[derive(Resource, Clone, Debug)]
+pub struct MatchPeers {
+    /// Players in handle order, the same order the GGRS session adds them in
+    pub players: Vec<PeerId>,
+    pub spectators: Vec<PeerId>,
+    /// Id of this side, among the players or the spectators
+    pub local: PeerId,
+}
+
+impl MatchPeers {
+    /// The player spectators get the confirmed inputs from
+    pub fn host(&self) -> PeerId {
+        self.players[0]
+    }
+}
+
+fn receive_lobby_messages(
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+    mut inbox: ResMut<LobbyInbox>,
+) {
+    for (peer, packet) in socket.channel(LOBBY_CHANNEL).receive() {
+        let message = std::str::from_utf8(&packet)
+            .ok()
+            .and_then(|text| ron::from_str(text).ok());
+        match message {
+            Some(message) => inbox.0.push((peer, message)),
+            None => warn!("ignoring malformed lobby message from {peer}"),
+        }
+    }
+}
+
+/// Someone connected to the same lobby
+struct LobbyPeer {
+    id: PeerId,
+    /// Unknown until their first message arrives
+    nickname: Option<String>,
+    /// Stage they voted for, set while they're ready
+    ready: Option<String>,
+    settings: Option<Settings>,
+    spectator: bool,
+    /// Why they can't play with the local side, set when their handshake doesn't match
+    refused: Option<String>,
+}
+
+impl LobbyPeer {
+    fn new(id: PeerId) -> Self {
+        LobbyPeer {
+            id,
+            nickname: None,
+            ready: None,
+            settings: None,
+            spectator: false,
+            refused: None,
+        }
+    }
+
+    fn name(&self) -> String {
+        self.nickname
+            .clone()
+            .unwrap_or_else(|| format!("Player {}", short_id(self.id)))
+    }
+}
+
+/// Lobby state while waiting for everyone to connect and ready up
+#[derive(Resource, Default)]
+struct Lobby {
+    /// Index into `Stages` of the local pick
+    stage: usize,
+    ready: bool,
+    nickname: String,
+    /// Whether typed characters currently go into the nickname
+    editing_name: bool,
+    /// Whether the local player only watches
+    spectating: bool,
+    peers: Vec<LobbyPeer>,
+    /// Last thing worth telling the player, like someone leaving
+    notice: Option<String>,
+    /// Stage the match was started on, along with who started it
+    start: Option<(PeerId, String)>,
+}
+
+impl Lobby {
+    /// Messages that bring a peer up to date with the local player
+    fn introductions(&self, stages: &Stages, settings: &Settings) -> [LobbyMessage; 3] {
+        let status = if self.spectating {
+            LobbyMessage::Spectating
+        } else {
+            LobbyMessage::Ready(self.ready.then(|| stages.0[self.stage].name.clone()))
+        };
+        [
+            LobbyMessage::Handshake(Handshake::new(settings)),
+            LobbyMessage::Nickname(self.nickname.clone()),
+            status,
+        ]
+    }
+
+    /// Everyone in the lobby who plays, spectators don't count
+    fn player_count(&self) -> usize {
+        let peers = self.peers.iter().filter(|p| !p.spectator).count();
+        peers + usize::from(!self.spectating)
+    }
+
+    /// Settings of the player with the lowest id, which everyone plays with.
+    /// `None` until the handshake of every player is known and matches.
+    fn host_settings(&self, own_id: PeerId, own: &Settings) -> Option<Settings> {
+        let mut candidates = Vec::new();
+        if !self.spectating {
+            candidates.push((own_id, *own));
+        }
+        for peer in self.peers.iter().filter(|p| !p.spectator) {
+            candidates.push((peer.id, peer.settings?));
+        }
+        candidates
+            .into_iter()
+            .min_by_key(|(id, _)| *id)
+            .map(|(_, settings)| settings)
+    }
+}
+
+/// Settings the match is played with, negotiated in the lobby
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct MatchSettings(pub Settings);
+
+/// Shown when coming back to the lobby, for explaining why the game went back there
+#[derive(Resource)]
+pub struct LobbyNotice(pub String);
+
+/// First few characters of a peer id, enough to tell peers apart
+fn short_id(peer: PeerId) -> String {
+    peer.to_string().chars().take(8).collect()
+}
 
 /// Marker component
 #[derive(Component)]
 struct LobbyText;
+/// Text listing everyone in the lobby
+#[derive(Component)]
+struct LobbyPlayersText;
 /// Marker component for lobby UI
 #[derive(Component)]
 struct LobbyUI;
@@ -14,61 +271,110 @@ pub struct LobbyPlugin;
 
 impl Plugin for LobbyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            OnEnter(AppState::Lobby),
-            (lobby_startup, start_matchbox_socket),
-        )
-        .add_systems(Update, lobby_system.run_if(in_state(AppState::Lobby)))
-        .add_systems(OnExit(AppState::Lobby), lobby_cleanup);
+        app.init_resource::<LobbyInbox>()
+            .add_systems(
+                OnEnter(AppState::Lobby),
+                (
+                    lobby_startup,
+                    // coming back from character select keeps the connection to the peers
+                    start_matchbox_socket
+                        .run_if(not(resource_exists::<MatchboxSocket<MultipleChannels>>())),
+                    start_lobby,
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    receive_lobby_messages
+                        .run_if(resource_exists::<MatchboxSocket<MultipleChannels>>()),
+                    (lobby_input, lobby_system, update_lobby_ui)
+                        .chain()
+                        .run_if(in_state(AppState::Lobby)),
+                )
+                    .chain(),
+            )
+            .add_systems(OnExit(AppState::Lobby), lobby_cleanup);
     }
 }
 
-fn start_matchbox_socket(mut commands: Commands, args: Res<Args>) {
-    let room_id = match &args.room {
-        Some(id) => id.clone(),
-        None => format!("bevy_ggrs?next={}", &args.players),
-    };
-
-    let room_url = format!("{}/{}", &args.matchbox, room_id);
+fn start_matchbox_socket(mut commands: Commands, args: Res<Args>, room: Res<Room>) {
+    let room_url = room.url(&args.matchbox, args.players);
     info!("connecting to matchbox server: {room_url:?}");
 
-    commands.insert_resource(MatchboxSocket::new_ggrs(room_url));
+    let socket = WebRtcSocketBuilder::new(room_url)
+        .add_ggrs_channel()
+        .add_reliable_channel();
+    commands.insert_resource(MatchboxSocket::from(socket));
+}
+
+fn start_lobby(
+    mut commands: Commands,
+    args: Res<Args>,
+    mode: Res<GameMode>,
+    stages: Res<Stages>,
+    settings: Res<Settings>,
+    notice: Option<Res<LobbyNotice>>,
+    mut socket: Option<ResMut<MatchboxSocket<MultipleChannels>>>,
+) {
+    // peers still connected from before need to be listed again, they don't connect twice
+    let peers = socket
+        .as_mut()
+        .map(|socket| socket.connected_peers().collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .map(LobbyPeer::new)
+        .collect();
+    // picking a stage on the command line picks it as the lobby's starting choice
+    let lobby = Lobby {
+        stage: stages.index_or_default(args.stage.as_deref()),
+        nickname: args.name.clone().unwrap_or_else(|| "Player".to_string()),
+        notice: notice.map(|notice| notice.0.clone()),
+        spectating: *mode == GameMode::Spectate,
+        peers,
+        ..default()
+    };
+    if let Some(socket) = socket.as_mut() {
+        for message in lobby.introductions(&stages, &settings) {
+            message.broadcast(socket);
+        }
+    }
+    commands.insert_resource(lobby);
+    commands.remove_resource::<LobbyNotice>();
 }
 
 fn lobby_startup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    // All this is just for spawning centered text.
+    let font = asset_server.load("fonts/quicksand-light.ttf");
+    let text_style = |font_size: f32| TextStyle {
+        font: font.clone(),
+        font_size,
+        color: Color::BLACK,
+    };
+
     commands
         .spawn(NodeBundle {
             style: Style {
                 width: Val::Percent(100.0),
                 height: Val::Percent(100.0),
                 position_type: PositionType::Absolute,
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::FlexEnd,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::SpaceEvenly,
+                align_items: AlignItems::Center,
                 ..default()
             },
             background_color: Color::rgb(0.43, 0.41, 0.38).into(),
             ..default()
         })
         .with_children(|parent| {
-            parent
-                .spawn(TextBundle {
-                    style: Style {
-                        align_self: AlignSelf::Center,
-                        justify_content: JustifyContent::Center,
-                        ..default()
-                    },
-                    text: Text::from_section(
-                        "Entering lobby...",
-                        TextStyle {
-                            font: asset_server.load("fonts/quicksand-light.ttf"),
-                            font_size: 96.,
-                            color: Color::BLACK,
-                        },
-                    ),
-                    ..default()
-                })
-                .insert(LobbyText);
+            parent.spawn(TextBundle::from_section("Lobby", text_style(64.0)));
+            parent.spawn((
+                TextBundle::from_section("", text_style(36.0)),
+                LobbyPlayersText,
+            ));
+            parent.spawn((
+                TextBundle::from_section("Entering lobby...", text_style(32.0))
+                    .with_text_alignment(TextAlignment::Center),
+                LobbyText,
+            ));
         })
         .insert(LobbyUI);
 }
@@ -77,54 +383,417 @@ fn lobby_cleanup(query: Query<Entity, With<LobbyUI>>, mut commands: Commands) {
     for entity in query.iter() {
         commands.entity(entity).despawn_recursive();
     }
+    commands.remove_resource::<Lobby>();
+}
+
+/// Stage picking, readying up, typing a nickname, copying the room code and leaving
+#[allow(clippy::too_many_arguments)]
+fn lobby_input(
+    keys: Res<Input<KeyCode>>,
+    mut typed: EventReader<ReceivedCharacter>,
+    stages: Res<Stages>,
+    room: Res<Room>,
+    mut lobby: ResMut<Lobby>,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+    mut clipboard: NonSendMut<Clipboard>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    if lobby.editing_name {
+        for typed in typed.iter() {
+            if (typed.char.is_alphanumeric() || typed.char == ' ')
+                && lobby.nickname.chars().count() < MAX_NICKNAME_LEN
+            {
+                lobby.nickname.push(typed.char);
+            }
+        }
+        if keys.just_pressed(KeyCode::Back) {
+            lobby.nickname.pop();
+        }
+        if keys.any_just_pressed([KeyCode::Return, KeyCode::Tab, KeyCode::Escape]) {
+            lobby.editing_name = false;
+            if lobby.nickname.trim().is_empty() {
+                lobby.nickname = "Player".to_string();
+            }
+            LobbyMessage::Nickname(lobby.nickname.clone()).broadcast(&mut socket);
+        }
+        return;
+    }
+    // the keys pressed to get here shouldn't end up in the name later
+    typed.clear();
+
+    if keys.just_pressed(KeyCode::Escape) {
+        info!("leaving the lobby");
+        app_state.set(AppState::MainMenu);
+        return;
+    }
+    if keys.just_pressed(KeyCode::Tab) {
+        lobby.editing_name = true;
+        return;
+    }
+    if let (Room::Private(code), true) = (room.as_ref(), ctrl_pressed(&keys, KeyCode::C)) {
+        lobby.notice = Some(match clipboard.copy(&format_code(code)) {
+            Ok(()) => "Copied the room code".to_string(),
+            Err(err) => format!("Couldn't copy the room code: {err}"),
+        });
+        return;
+    }
+    if lobby.spectating {
+        return;
+    }
+
+    if !lobby.ready {
+        let count = stages.0.len();
+        if keys.any_just_pressed([KeyCode::A, KeyCode::Left]) {
+            lobby.stage = (lobby.stage + count - 1) % count;
+        }
+        if keys.any_just_pressed([KeyCode::D, KeyCode::Right]) {
+            lobby.stage = (lobby.stage + 1) % count;
+        }
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        lobby.ready = !lobby.ready;
+        let vote = lobby.ready.then(|| stages.0[lobby.stage].name.clone());
+        LobbyMessage::Ready(vote).broadcast(&mut socket);
+    }
 }
 
 fn lobby_system(
     mut app_state: ResMut<NextState<AppState>>,
     args: Res<Args>,
-    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    stages: Res<Stages>,
+    settings: Res<Settings>,
+    mut lobby: ResMut<Lobby>,
+    mut inbox: ResMut<LobbyInbox>,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
     mut commands: Commands,
-    mut query: Query<&mut Text, With<LobbyText>>,
 ) {
     // regularly call update_peers to update the list of connected peers
     for (peer, new_state) in socket.update_peers() {
-        // you can also handle the specific dis(connections) as they occur:
         match new_state {
-            PeerState::Connected => info!("peer {peer} connected"),
-            PeerState::Disconnected => info!("peer {peer} disconnected"),
+            PeerState::Connected => {
+                info!("peer {peer} connected");
+                for message in lobby.introductions(&stages, &settings) {
+                    message.send(&mut socket, peer);
+                }
+                lobby.peers.push(LobbyPeer::new(peer));
+            }
+            PeerState::Disconnected => {
+                info!("peer {peer} disconnected");
+                inbox.0.retain(|(sender, _)| *sender != peer);
+                if let Some(index) = lobby.peers.iter().position(|p| p.id == peer) {
+                    let left = lobby.peers.remove(index);
+                    lobby.notice = Some(format!("{} left the lobby", left.name()));
+                }
+            }
         }
     }
 
-    let connected_peers = socket.connected_peers().count();
-    let remaining = args.players - (connected_peers + 1);
-    query.single_mut().sections[0].value = format!("Waiting for {remaining} more player(s)",);
-    if remaining > 0 {
+    let lobby = &mut *lobby;
+    inbox.0.retain(|(peer, message)| {
+        // kept around when the peer's connection hasn't shown up yet
+        let Some(sender) = lobby.peers.iter_mut().find(|p| p.id == *peer) else {
+            return true;
+        };
+        match message {
+            LobbyMessage::Nickname(nickname) => {
+                let nickname: String = nickname.chars().take(MAX_NICKNAME_LEN).collect();
+                info!("peer {peer} is called {nickname}");
+                sender.nickname = Some(nickname);
+                false
+            }
+            LobbyMessage::Ready(vote) => {
+                info!("peer {peer} ready: {vote:?}");
+                sender.ready = vote.clone();
+                false
+            }
+            LobbyMessage::Handshake(handshake) => {
+                match handshake.mismatch(&Handshake::new(&settings)) {
+                    None => {
+                        info!("peer {peer} runs the same build");
+                        sender.settings = Some(handshake.settings);
+                        sender.refused = None;
+                    }
+                    Some(reason) => {
+                        warn!("refusing peer {peer}: {reason:?}");
+                        lobby.notice = Some(format!("Can't play with {}, {reason}", sender.name()));
+                        sender.settings = None;
+                        sender.refused = Some(reason);
+                    }
+                }
+                false
+            }
+            LobbyMessage::Spectating => {
+                info!("peer {peer} is spectating");
+                sender.spectator = true;
+                sender.ready = None;
+                false
+            }
+            LobbyMessage::Start { stage } => {
+                info!("peer {peer} started the match on {stage}");
+                lobby.start = Some((*peer, stage.clone()));
+                false
+            }
+            _ => true,
+        }
+    });
+
+    if lobby.player_count() != args.players {
         return;
     }
+    let Some(id) = socket.id() else {
+        return;
+    };
+    let Some(host_settings) = lobby.host_settings(id, &settings) else {
+        return;
+    };
 
-    info!("All peers have joined, going in-game");
+    let mut players: Vec<PeerId> = lobby
+        .peers
+        .iter()
+        .filter(|p| !p.spectator)
+        .map(|p| p.id)
+        .chain((!lobby.spectating).then_some(id))
+        .collect();
+    players.sort();
+    let host = players[0];
+    // ready states change while they're on their way, so two peers could each see everyone ready
+    // with different votes. Only the host decides, everyone else goes with its start message.
+    let stage = if host == id {
+        let everyone_ready = lobby.ready
+            && lobby
+                .peers
+                .iter()
+                .filter(|p| !p.spectator)
+                .all(|p| p.ready.is_some());
+        if !everyone_ready {
+            return;
+        }
+        let mut votes: Vec<(PeerId, usize)> = lobby
+            .peers
+            .iter()
+            .filter(|p| !p.spectator)
+            .map(|p| (p.id, stages.index_or_default(p.ready.as_deref())))
+            .chain(Some((id, lobby.stage)))
+            .collect();
+        votes.sort();
+        // when the picks differ, one of them is chosen
+        let total: usize = votes.iter().map(|(_, stage)| stage).sum();
+        let (_, stage) = votes[total % votes.len()];
+        LobbyMessage::Start {
+            stage: stages.0[stage].name.clone(),
+        }
+        .broadcast(&mut socket);
+        stage
+    } else {
+        match &lobby.start {
+            Some((sender, stage)) if *sender == host => stages.index_or_default(Some(stage)),
+            _ => return,
+        }
+    };
+    info!(
+        "Everyone is ready, going to character select on {}",
+        stages.0[stage].name
+    );
+    commands.insert_resource(Stage(stages.0[stage].clone()));
+    commands.insert_resource(MatchSettings(settings.negotiated(&host_settings)));
+
+    // a mismatched spectator can't follow the match, but doesn't keep the players from it either
+    let mut spectators: Vec<PeerId> = lobby
+        .peers
+        .iter()
+        .filter(|p| p.spectator && p.refused.is_none())
+        .map(|p| p.id)
+        .chain(lobby.spectating.then_some(id))
+        .collect();
+    spectators.sort();
+    commands.insert_resource(MatchPeers {
+        players,
+        spectators,
+        local: id,
+    });
 
-    // extract final player list
-    let players = socket.players();
+    app_state.set(AppState::CharacterSelect);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_lobby_ui(
+    args: Res<Args>,
+    stages: Res<Stages>,
+    settings: Res<Settings>,
+    lobby: Res<Lobby>,
+    room: Res<Room>,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+    mut players: Query<&mut Text, (With<LobbyPlayersText>, Without<LobbyText>)>,
+    mut status: Query<&mut Text, (With<LobbyText>, Without<LobbyPlayersText>)>,
+) {
+    let ready_mark = |ready: bool, spectator: bool| match (ready, spectator) {
+        (_, true) => "Spectating",
+        (true, _) => "Ready",
+        _ => "Not ready",
+    };
+    let peer_mark = |peer: &LobbyPeer| match (&peer.refused, peer.spectator) {
+        (Some(_), true) => "Can't watch with you",
+        (Some(_), false) => "Can't play with you",
+        // an older build's handshake can't even be read, so they stay here
+        (None, _) if peer.settings.is_none() => "Checking their build",
+        (None, _) => ready_mark(peer.ready.is_some(), peer.spectator),
+    };
+    let own_id = socket.id().map_or("connecting".to_string(), short_id);
+    let cursor = if lobby.editing_name { "_" } else { "" };
+    let mut lines = vec![format!(
+        "{}{cursor} ({own_id}, you) - {}",
+        lobby.nickname,
+        ready_mark(lobby.ready, lobby.spectating)
+    )];
+    for peer in &lobby.peers {
+        lines.push(format!(
+            "{} ({}) - {}",
+            peer.name(),
+            short_id(peer.id),
+            peer_mark(peer)
+        ));
+    }
+    let players = lobby.player_count();
+    if players < args.players {
+        lines.push(format!(
+            "Waiting for {} more player(s)",
+            args.players - players
+        ));
+    } else if players > args.players {
+        lines.push(format!(
+            "Too many players, this room is for {}, join as a spectator instead",
+            args.players
+        ));
+    }
+    players.single_mut().sections[0].value = lines.join("\n");
+
+    let controls = if lobby.editing_name {
+        "Type your name, Enter when done"
+    } else if lobby.spectating {
+        "Watching once the players are ready, Tab to change your name, Escape to leave"
+    } else if lobby.ready {
+        "Enter to stop being ready, Escape to leave"
+    } else {
+        "A/D to pick a stage, Enter to ready up, Tab to change your name, Escape to leave"
+    };
+    let notice = lobby
+        .notice
+        .as_ref()
+        .map_or(String::new(), |notice| format!("{notice}\n"));
+    // the host's settings win, so it's worth pointing out when they aren't the local ones
+    let session = match socket
+        .id()
+        .and_then(|id| lobby.host_settings(id, &settings))
+    {
+        Some(host) => {
+            let desync_check = match host.desync_interval {
+                0 => "off".to_string(),
+                interval => format!("every {interval} frames"),
+            };
+            let differs = if settings.negotiated(&host) != *settings {
+                " (the host's, not yours)"
+            } else {
+                ""
+            };
+            format!(
+                "Session: {} fps, max prediction {}, desync check {desync_check}{differs}\n",
+                host.fps, host.max_prediction
+            )
+        }
+        None => String::new(),
+    };
+    let room = match room.as_ref() {
+        Room::Private(code) => format!("Room code: {} (Ctrl+C to copy)\n", format_code(code)),
+        Room::Named(name) => format!("Room: {name}\n"),
+        // the host's own socket connects to its server on this machine
+        Room::Lan(addr) if addr.ip().is_loopback() => {
+            "Hosting on the LAN, others find this game under LAN Games\n".to_string()
+        }
+        Room::Lan(addr) => format!("LAN game hosted at {}\n", addr.ip()),
+        Room::Public => String::new(),
+    };
+    status.single_mut().sections[0].value = format!(
+        "{room}{notice}{session}Stage: {}\n{controls}",
+        stages.0[lobby.stage].name
+    );
+}
 
-    let mut session_builder = configure_session(args.players);
+/// The socket's GGRS channel. The socket only hands it out once, so it's taken for the first
+/// session and shared by every session after it, letting rematches run on the same connection.
+#[derive(Resource, Clone)]
+pub struct GgrsChannel(Arc<Mutex<WebRtcChannel>>);
 
-    for (i, player) in players.into_iter().enumerate() {
+impl GgrsChannel {
+    /// The channel for a new session, taken out of `socket` if no session had it yet
+    pub fn get(
+        commands: &mut Commands,
+        socket: &mut MatchboxSocket<MultipleChannels>,
+        shared: Option<&GgrsChannel>,
+    ) -> GgrsChannel {
+        let Some(shared) = shared else {
+            let channel = socket.take_channel(GGRS_CHANNEL).unwrap();
+            let channel = GgrsChannel(Arc::new(Mutex::new(channel)));
+            commands.insert_resource(channel.clone());
+            return channel;
+        };
+        let mut channel = shared.clone();
+        // whatever the last session left unread would only confuse the new one
+        channel.receive_all_messages();
+        channel
+    }
+}
+
+impl NonBlockingSocket<PeerId> for GgrsChannel {
+    fn send_to(&mut self, msg: &Message, addr: &PeerId) {
+        self.0.lock().unwrap().send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        self.0.lock().unwrap().receive_all_messages()
+    }
+}
+
+/// Starts the GGRS session on the lobby's socket
+pub fn start_p2p_session(
+    mut commands: Commands,
+    args: Res<Args>,
+    settings: Res<MatchSettings>,
+    peers: Res<MatchPeers>,
+    shared_channel: Option<Res<GgrsChannel>>,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+) {
+    let id = socket.id().expect("socket has no id");
+
+    let mut session_builder = configure_session(&mut commands, args.players, &settings.0);
+
+    for (i, &player) in peers.players.iter().enumerate() {
+        let player = if player == id {
+            PlayerType::Local
+        } else {
+            PlayerType::Remote(player)
+        };
         session_builder = session_builder
             .add_player(player, i)
             .expect("failed to add player");
     }
+    // only the host sends spectators inputs, they get the handles after the players'.
+    // Spectators that left since the lobby would keep a rematch from ever starting.
+    if peers.host() == id {
+        let connected: Vec<PeerId> = socket.connected_peers().collect();
+        let spectators = peers.spectators.iter().filter(|s| connected.contains(s));
+        for (i, &spectator) in spectators.enumerate() {
+            session_builder = session_builder
+                .add_player(PlayerType::Spectator(spectator), args.players + i)
+                .expect("failed to add spectator");
+        }
+    }
 
-    let channel = socket.take_channel(0).unwrap();
+    let channel = GgrsChannel::get(&mut commands, &mut socket, shared_channel.as_deref());
 
     // start the GGRS session
     let session = session_builder
-        .with_desync_detection_mode(DesyncDetection::On { interval: 1 })
         .start_p2p_session(channel)
         .expect("failed to start session");
 
     commands.insert_resource(Session::P2P(session));
-
-    // transition to in-game state
-    app_state.set(AppState::InGame);
 }
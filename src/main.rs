@@ -1,20 +1,67 @@
-use crate::{input::*, lobby::LobbyPlugin};
+use crate::{
+    camera::CameraPlugin,
+    character::*,
+    combat::*,
+    connection::{ConnectionPlugin, MatchEnded},
+    direct::{start_direct_session, DirectConnectPlugin, DirectSocket},
+    discovery::{DiscoveryPlugin, LanHost},
+    hud::HudPlugin,
+    input::*,
+    lobby::{start_p2p_session, GgrsChannel, LobbyInbox, LobbyPlugin, MatchPeers},
+    menu::{GameMode, MenuPlugin},
+    meter::*,
+    movement::*,
+    netstats::{count_simulated_frames, NetStatsPlugin},
+    pause::{PausePlugin, PausedSession},
+    projectile::*,
+    pushbox::*,
+    rematch::{MatchOverConfirmations, RematchPlugin},
+    replay::*,
+    room::RoomPlugin,
+    settings::{Settings, SettingsPlugin},
+    spectator::{start_spectator_session, DelayedSpectatorSession, SpectatorPlugin},
+    stage::*,
+    throw::*,
+};
 use args::*;
 use bevy::ecs::schedule::ScheduleLabel;
-use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
+use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*, sprite::Anchor};
 use bevy_ggrs::{
-    prelude::*, GgrsComponentChecksumHashPlugin, GgrsComponentMapEntitiesPlugin,
-    GgrsComponentSnapshotClonePlugin, GgrsResourceSnapshotClonePlugin,
+    ggrs::DesyncDetection, prelude::*, GgrsComponentChecksumHashPlugin,
+    GgrsComponentMapEntitiesPlugin, GgrsComponentSnapshotClonePlugin,
+    GgrsResourceChecksumHashPlugin, GgrsResourceSnapshotClonePlugin, Rollback,
 };
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_matchbox::prelude::*;
 use bevy_xpbd_2d::{math::*, prelude::*};
+use clap::CommandFactory;
+use std::time::Duration;
 
 mod args;
+mod camera;
+mod character;
+mod character_select;
+mod combat;
+mod connection;
+mod direct;
+mod discovery;
+mod hud;
 mod input;
 mod lobby;
-
-const FPS: usize = 60;
+mod menu;
+mod meter;
+mod movement;
+mod netstats;
+mod pause;
+mod projectile;
+mod pushbox;
+mod rematch;
+mod replay;
+mod room;
+mod settings;
+mod spectator;
+mod stage;
+mod throw;
 
 pub type GgrsConfig = bevy_ggrs::GgrsConfig<InferiInput, PeerId>;
 
@@ -47,96 +94,143 @@ struct FrameCount {
     frame: usize,
 }
 
-#[derive(Component, Clone)]
-struct AnimationIndices {
-    first: usize,
-    last: usize,
-}
+/// Fighters in a match, character select, the HUD and the meter keep one of everything for each
+pub const PLAYERS: usize = 2;
 
-#[derive(Component, Deref, DerefMut)]
-struct AnimationTimer(Timer);
+/// How much a crouching fighter's sprite gets squashed, until there's crouching art
+const CROUCH_SQUASH: f32 = 0.7;
+/// Same for a fighter lying on the floor after a knockdown
+const KNOCKDOWN_SQUASH: f32 = 0.3;
 
-fn setup_scene(mut commands: Commands, frame: Res<FrameCount>, asset_server: Res<AssetServer>) {
+fn setup_scene(
+    mut commands: Commands,
+    frame: Res<FrameCount>,
+    stage: Res<Stage>,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+) {
     // Spawn box arround players, this code is found in many places like xpdb example programs
     if **frame != 0 {
         return;
     }
 
-    info!("Setting up scene");
+    info!("Setting up scene for {}", stage.name);
     let square_sprite = Sprite {
         color: Color::rgb(0.7, 0.7, 0.8),
-        custom_size: Some(Vec2::splat(50.0)),
+        custom_size: Some(Vec2::ONE),
         ..default()
     };
 
-    // Spawn the background sprite
-    // Hard coded the size for now, same with img cause we dont have a secondary stage
-    commands.spawn(SpriteBundle {
-        texture: asset_server.load("Stage Backgrounds/Background.png"),
-        transform: Transform::from_scale(Vec3::new(1.76, 2.2, 1.0)),
-        ..Default::default()
-    });
-    // Ceiling
-    commands
-        .spawn((
-            SpriteBundle {
-                sprite: square_sprite.clone(),
-                transform: Transform::from_scale(Vec3::new(20.0, 1.0, 1.0)),
-                ..default()
-            },
-            RigidBody::Static,
-            Position(Vector::Y * 50.0 * 6.0),
-            Collider::cuboid(50.0 * 20.0, 50.0),
-        ))
-        .add_rollback();
-
-    // Floor
-    commands
-        .spawn((
-            SpriteBundle {
-                sprite: square_sprite.clone(),
-                transform: Transform::from_scale(Vec3::new(20.0, 1.0, 1.0)),
-                ..default()
-            },
-            RigidBody::Static,
-            Position(Vector::NEG_Y * 50.0 * 6.0),
-            Collider::cuboid(50.0 * 20.0, 50.0),
-        ))
-        .add_rollback();
-
-    // Left wall
-    commands
-        .spawn((
-            SpriteBundle {
-                sprite: square_sprite.clone(),
-                transform: Transform::from_scale(Vec3::new(1.0, 11.0, 1.0)),
-                ..default()
-            },
-            RigidBody::Static,
-            Position(Vector::NEG_X * 50.0 * 9.5),
-            Collider::cuboid(50.0, 50.0 * 11.0),
-        ))
-        .add_rollback();
-
-    // Right wall
-    commands
-        .spawn((
-            SpriteBundle {
-                sprite: square_sprite,
-                transform: Transform::from_scale(Vec3::new(1.0, 11.0, 1.0)),
-                ..default()
+    for layer in &stage.layers {
+        let (r, g, b) = layer.tint;
+        let color = Color::rgb(r, g, b);
+        let transform = Transform::from_translation(layer.offset.extend(layer.z))
+            .with_scale(layer.scale.extend(1.0));
+        let parallax = ParallaxLayer {
+            origin: transform.translation,
+            parallax: layer.parallax,
+            drift: layer.drift,
+            wrap: stage.bounds().width(),
+        };
+        let texture = asset_server.load(&layer.image);
+
+        match layer.animation {
+            Some(animation) => {
+                let texture_atlas = TextureAtlas::from_grid(
+                    texture,
+                    animation.tile_size,
+                    animation.columns,
+                    animation.rows,
+                    None,
+                    None,
+                );
+                commands.spawn((
+                    SpriteSheetBundle {
+                        texture_atlas: texture_atlases.add(texture_atlas),
+                        sprite: TextureAtlasSprite { color, ..default() },
+                        transform,
+                        ..default()
+                    },
+                    parallax,
+                    AnimatedLayer {
+                        frames: animation.columns * animation.rows,
+                        fps: animation.fps,
+                    },
+                ));
+            }
+            None => {
+                commands.spawn((
+                    SpriteBundle {
+                        texture,
+                        sprite: Sprite { color, ..default() },
+                        transform,
+                        ..default()
+                    },
+                    parallax,
+                ));
+            }
+        }
+    }
+
+    if let Some(music) = &stage.music {
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load(music),
+                settings: PlaybackSettings::LOOP,
             },
-            RigidBody::Static,
-            Position(Vector::X * 50.0 * 9.5),
-            Collider::cuboid(50.0, 50.0 * 11.0),
-        ))
-        .add_rollback();
+            StageMusic,
+        ));
+    }
+
+    let bounds = stage.bounds();
+    let width = bounds.width();
+    let height = stage.ceiling - stage.floor;
+    let middle = (stage.ceiling + stage.floor) / 2.0;
+    let blocks = [
+        // Ceiling
+        (
+            Vector::new(0.0, stage.ceiling + WALL_THICKNESS / 2.0),
+            Vector::new(width, WALL_THICKNESS),
+        ),
+        // Floor
+        (
+            Vector::new(0.0, stage.floor - WALL_THICKNESS / 2.0),
+            Vector::new(width, WALL_THICKNESS),
+        ),
+        // Left wall
+        (
+            Vector::new(-stage.walls - WALL_THICKNESS / 2.0, middle),
+            Vector::new(WALL_THICKNESS, height),
+        ),
+        // Right wall
+        (
+            Vector::new(stage.walls + WALL_THICKNESS / 2.0, middle),
+            Vector::new(WALL_THICKNESS, height),
+        ),
+    ];
+    for (position, size) in blocks {
+        commands
+            .spawn((
+                SpriteBundle {
+                    sprite: square_sprite.clone(),
+                    transform: Transform::from_scale(size.extend(1.0)),
+                    ..default()
+                },
+                RigidBody::Static,
+                Position(position),
+                Collider::cuboid(size.x, size.y),
+            ))
+            .add_rollback();
+    }
 }
 
 fn spawn_characters(
     mut commands: Commands,
     frame_count: Res<FrameCount>,
     asset_server: Res<AssetServer>,
+    characters: Res<Characters>,
+    selections: Res<Selections>,
+    stage: Res<Stage>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
 ) {
     if **frame_count != 0 {
@@ -145,137 +239,266 @@ fn spawn_characters(
     }
     info!("Spawning characters");
 
-    // Load the combined texture containing both default character and punch animation
-    let texture_handle = asset_server.load("fighters/redNinja.png");
-    let texture_atlas = TextureAtlas::from_grid(
-        texture_handle.clone(),
-        Vec2::new(64.0, 64.0),
-        5,
-        1,
-        None,
-        None,
-    );
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
-    let default_indices = AnimationIndices { first: 0, last: 0 }; //dont want it to play any animation at the begining
-
-    // Spawn Player 1
-    commands
-        .spawn((
-            Player { handle: 0 },
-            SpriteSheetBundle {
-                texture_atlas: texture_atlas_handle.clone(),
-                sprite: TextureAtlasSprite::new(default_indices.first),
-                transform: Transform::from_scale(Vec3::splat(2.0)),
-                ..Default::default()
-            },
-            default_indices.clone(),
-            AnimationTimer(Timer::from_seconds(0.5, TimerMode::Repeating)),
-        ))
-        .insert(RigidBody::Dynamic)
-        .insert(Position(Vector::new(-150.0, 0.0)))
-        .insert(PrevPos(Vector::new(-150.0, 0.0)))
-        .insert(Rotation::default())
-        .insert(Collider::cuboid(30.0, 50.0))
-        .insert(Actor)
-        .insert(LockedAxes::ROTATION_LOCKED);
-
-    // Spawn player 2
-    commands
-        .spawn((
-            Player { handle: 1 },
-            SpriteSheetBundle {
-                texture_atlas: texture_atlas_handle.clone(),
-                sprite: TextureAtlasSprite::new(default_indices.first),
-                transform: Transform::from_scale(Vec3::splat(2.0)),
-                ..Default::default()
-            },
-            default_indices,
-            AnimationTimer(Timer::from_seconds(0.5, TimerMode::Repeating)),
-        ))
-        .insert(RigidBody::Dynamic)
-        .insert(Position(Vector::new(-150.0, 0.0)))
-        .insert(PrevPos(Vector::new(-150.0, 0.0)))
-        .insert(Rotation::default())
-        .insert(Collider::cuboid(30.0, 50.0))
-        .insert(Actor)
-        .insert(LockedAxes::ROTATION_LOCKED);
+    for (handle, selection) in selections.0.iter().enumerate() {
+        let character_id = selection.character;
+        let character = characters.get(&character_id);
+
+        // Load the combined texture containing both default character and punch animation
+        let texture_handle = asset_server.load(&character.sprite_sheet);
+        let texture_atlas = TextureAtlas::from_grid(
+            texture_handle.clone(),
+            Vec2::new(character.tile_size.0, character.tile_size.1),
+            character.columns,
+            character.rows,
+            None,
+            None,
+        );
+        let texture_atlas_handle = texture_atlases.add(texture_atlas);
+
+        commands
+            .spawn((
+                Player { handle },
+                SpriteSheetBundle {
+                    texture_atlas: texture_atlas_handle,
+                    sprite: TextureAtlasSprite {
+                        color: character.palette_color(selection.palette),
+                        ..TextureAtlasSprite::new(character.idle_sprite)
+                    },
+                    transform: Transform::from_scale(Vec3::splat(2.0)),
+                    ..Default::default()
+                },
+                Health(MAX_HEALTH),
+                character.standing_hurtbox,
+                AttackState::default(),
+                Hitstop::default(),
+                FighterState::default(),
+                ThrowInvuln::default(),
+            ))
+            .insert((
+                character_id,
+                InputHistory::default(),
+                Movement::default(),
+                AirDashUsed::default(),
+                Invulnerable::default(),
+                Crouching::default(),
+            ))
+            .insert(RigidBody::Dynamic)
+            .insert(Position(stage.spawn_point(handle)))
+            .insert(PrevPos(stage.spawn_point(handle)))
+            .insert(Rotation::default())
+            .insert(Collider::cuboid(30.0, 50.0))
+            .insert(CollisionLayers::new([Layer::Fighter], [Layer::World]))
+            .insert(Actor)
+            .insert(LockedAxes::ROTATION_LOCKED)
+            .add_rollback();
+    }
+}
+
+/// Starts a move, paying its meter cost and kicking off its super freeze.
+/// Nothing starts if the meter can't pay for it.
+fn start_move(
+    attack: &mut AttackState,
+    character: &CharacterData,
+    move_index: usize,
+    handle: usize,
+    meter: &mut SuperMeter,
+    super_freeze: &mut SuperFreeze,
+) {
+    let next = &character.moves[move_index];
+    if !meter.spend(handle, next.meter_cost) {
+        return;
+    }
+    super_freeze.frames = super_freeze.frames.max(next.super_freeze);
+    attack.start(move_index);
 }
 
 fn handle_player_input(
-    time: Res<Time>,
     inputs: Res<PlayerInputs<GgrsConfig>>,
+    characters: Res<Characters>,
+    stage: Res<Stage>,
+    mut meter: ResMut<SuperMeter>,
+    mut super_freeze: ResMut<SuperFreeze>,
     mut query: Query<(
-        &mut AnimationIndices,
-        &mut AnimationTimer,
-        &mut TextureAtlasSprite,
+        &mut AttackState,
+        &Hitstop,
+        &FighterState,
+        &Movement,
+        &Crouching,
+        &CharacterId,
+        &InputHistory,
+        &TextureAtlasSprite,
+        &Position,
         Option<&mut LinearVelocity>,
         &Player,
     )>,
 ) {
-    for (mut indices, mut timer, mut sprite, velocity, player) in &mut query.iter_mut() {
+    for (
+        mut attack,
+        hitstop,
+        state,
+        movement,
+        crouching,
+        character,
+        history,
+        sprite,
+        position,
+        velocity,
+        player,
+    ) in &mut query.iter_mut()
+    {
+        // frozen fighters ignore input and hold their current animation frame,
+        // stunned or thrown ones have their own systems driving them
+        if hitstop.active() || *state != FighterState::Idle {
+            continue;
+        }
+
         if let Some(input) = inputs.get(player.handle) {
             let buttons = input.0.buttons;
 
             //let buttons = inputs.iter().next().map_or(0, |input| input.0.buttons);
 
-            // Handle movement logic
-            if let Some(mut linear_velocity) = velocity {
-                // Handles movement(speed)
-                if buttons & INPUT_UP_JUST_PRESSED != 0 {
-                    linear_velocity.y += 125.0;
-                }
-                if buttons & INPUT_DOWN != 0 {
-                    linear_velocity.y -= 10.0;
-                }
-                if buttons & INPUT_LEFT != 0 {
-                    linear_velocity.x -= 10.0;
-                }
-                if buttons & INPUT_RIGHT != 0 {
-                    linear_velocity.x += 10.0;
+            let character = characters.get(character);
+            let stance = if crouching.0 {
+                Stance::Crouching
+            } else if is_grounded(position.0, &stage) {
+                Stance::Standing
+            } else {
+                Stance::Air
+            };
+
+            // a move in progress can only be left through one of its cancel rules,
+            // and only once it hit or got blocked inside its cancel window
+            if let Some(current) = attack.current {
+                let current = &character.moves[current];
+                if !attack.connected || !current.in_cancel_window(attack.frame) {
+                    continue;
                 }
 
-                // Handles changing directions
-                if buttons & INPUT_LEFT_JUST_PRESSED != 0 {
-                    sprite.flip_x = true;
+                if current.jump_cancel && buttons & INPUT_UP_JUST_PRESSED != 0 {
+                    attack.stop();
+                    if let Some(mut linear_velocity) = velocity {
+                        linear_velocity.y += character.jump_velocity;
+                    }
+                    continue;
                 }
 
-                if buttons & INPUT_RIGHT_JUST_PRESSED != 0 {
-                    sprite.flip_x = false;
+                let next = character
+                    .find_move(history, sprite.flip_x, stance, meter.get(player.handle))
+                    .filter(|next| current.can_cancel_into(&character.moves[*next]));
+                if let Some(next) = next {
+                    start_move(
+                        &mut attack,
+                        character,
+                        next,
+                        player.handle,
+                        &mut meter,
+                        &mut super_freeze,
+                    );
                 }
+                continue;
             }
 
-            // Check if the punch button is just pressed in the current frame
-            let punch_pressed = buttons & INPUT_PUNCH_JUST_PRESSED != 0;
+            // Handle movement logic, dashes set their own speed in update_movement
+            // and crouching fighters stay where they are
+            if let Some(mut linear_velocity) =
+                velocity.filter(|_| *movement == Movement::Neutral && !crouching.0)
+            {
+                // Handles movement(speed)
+                if buttons & INPUT_UP_JUST_PRESSED != 0 {
+                    linear_velocity.y += character.jump_velocity;
+                }
+                // only accelerate up to walking speed, anything faster is left alone
+                if buttons & INPUT_LEFT != 0 && linear_velocity.x > -character.walk_speed {
+                    linear_velocity.x = (linear_velocity.x - character.walk_acceleration)
+                        .max(-character.walk_speed);
+                }
+                if buttons & INPUT_RIGHT != 0 && linear_velocity.x < character.walk_speed {
+                    linear_velocity.x =
+                        (linear_velocity.x + character.walk_acceleration).min(character.walk_speed);
+                }
+            }
 
-            // Conditionally update animation_indices based on button press
-            if punch_pressed {
-                // Adjust the start and end index based on your sprite sheet
-                indices.first = 1;
-                indices.last = 4;
+            // backdashes are fully committed so they can't be cancelled into an attack
+            if matches!(movement, Movement::Backdash { .. }) {
+                continue;
             }
 
-            // Update the sprite index based on the animation_indices
-            timer.tick(time.delta());
-            if timer.just_finished() {
-                if sprite.index == indices.last {
-                    sprite.index = 0;
-                    indices.first = 0;
-                    indices.last = 0;
-                } else {
-                    sprite.index += 1
-                };
+            if let Some(move_index) =
+                character.find_move(history, sprite.flip_x, stance, meter.get(player.handle))
+            {
+                start_move(
+                    &mut attack,
+                    character,
+                    move_index,
+                    player.handle,
+                    &mut meter,
+                    &mut super_freeze,
+                );
             }
         }
     }
 }
 
+/// Picks the sprite to show from the fighter's current move or stance.
+/// Throws pick their own sprites so only fighters that are free or blocking get animated here.
+fn animate_fighters(
+    characters: Res<Characters>,
+    mut query: Query<(
+        &CharacterId,
+        &FighterState,
+        &AttackState,
+        &Crouching,
+        &mut TextureAtlasSprite,
+    )>,
+) {
+    for (character, state, attack, crouching, mut sprite) in &mut query {
+        let character = characters.get(character);
+
+        // there's no crouching or knockdown art yet, so the sprite gets squashed down
+        // while keeping its feet on the floor
+        let squash = if state.knocked_down() {
+            Some(KNOCKDOWN_SQUASH)
+        } else if crouching.0 {
+            Some(CROUCH_SQUASH)
+        } else {
+            None
+        };
+        if let Some(squash) = squash {
+            sprite.custom_size = Some(Vec2::new(
+                character.tile_size.0,
+                character.tile_size.1 * squash,
+            ));
+            sprite.anchor = Anchor::Custom(Vec2::new(0.0, (1.0 - squash) / (2.0 * squash)));
+        } else {
+            sprite.custom_size = None;
+            sprite.anchor = Anchor::Center;
+        }
+
+        if !matches!(state, FighterState::Idle | FighterState::Blockstun { .. }) {
+            continue;
+        }
+        sprite.index = match attack.current {
+            Some(move_index) => character.moves[move_index].sprite_index(attack.frame),
+            None if crouching.0 => character.crouch_sprite,
+            None => character.idle_sprite,
+        };
+    }
+}
+
 #[derive(Debug, Clone, Default, Eq, PartialEq, Hash, States)]
 pub enum AppState {
     #[default]
     Startup,
+    MainMenu,
+    Settings,
     Lobby,
+    RoomCode,
+    LanBrowser,
+    DirectConnect,
+    CharacterSelect,
     InGame,
     Paused,
+    MatchOver,
 }
 
 #[derive(ScheduleLabel, Clone, Debug, Hash, Eq, PartialEq)]
@@ -285,6 +508,12 @@ fn main() {
     // read query string or command line arguments
     let args = Args::get();
     info!("{args:?}");
+    // the ranges are checked while parsing, this catches settings that don't go together
+    let settings = Settings::from_args(&args).unwrap_or_else(|err| {
+        Args::command()
+            .error(clap::error::ErrorKind::ArgumentConflict, err)
+            .exit()
+    });
 
     App::new()
         .add_plugins((
@@ -297,7 +526,22 @@ fn main() {
             }),
             PhysicsPlugins::new(PhysicsSchedule),
             FrameTimeDiagnosticsPlugin,
+            MenuPlugin,
+            SettingsPlugin,
+            PausePlugin,
             LobbyPlugin,
+            RoomPlugin,
+            DirectConnectPlugin,
+            DiscoveryPlugin,
+            ConnectionPlugin,
+            NetStatsPlugin,
+            SpectatorPlugin,
+            RematchPlugin,
+            CameraPlugin,
+            ProjectilePlugin,
+            HudPlugin,
+            StagePlugin,
+            CharacterSelectPlugin,
             WorldInspectorPlugin::default(),
         ))
         .add_plugins(GgrsPlugin::<GgrsConfig>::default())
@@ -314,17 +558,85 @@ fn main() {
         .add_plugins(GgrsComponentMapEntitiesPlugin::<DistanceJoint>::default())
         .add_plugins(GgrsComponentSnapshotClonePlugin::<PrevPos>::default())
         .add_plugins(GgrsComponentChecksumHashPlugin::<PrevPos>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<TextureAtlasSprite>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<AttackState>::default())
+        .add_plugins(GgrsComponentChecksumHashPlugin::<AttackState>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<Hurtbox>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<Crouching>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<Hitstop>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<Health>::default())
+        .add_plugins(GgrsComponentChecksumHashPlugin::<Health>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<FighterState>::default())
+        .add_plugins(GgrsComponentChecksumHashPlugin::<FighterState>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<ThrowInvuln>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<Invulnerable>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<InputHistory>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<Movement>::default())
+        .add_plugins(GgrsComponentChecksumHashPlugin::<Movement>::default())
+        .add_plugins(GgrsComponentSnapshotClonePlugin::<AirDashUsed>::default())
         .add_plugins(GgrsResourceSnapshotClonePlugin::<FrameCount>::default())
+        .add_plugins(GgrsResourceSnapshotClonePlugin::<LastImpact>::default())
+        .add_plugins(GgrsResourceSnapshotClonePlugin::<SuperMeter>::default())
+        .add_plugins(GgrsResourceChecksumHashPlugin::<SuperMeter>::default())
+        .add_plugins(GgrsResourceSnapshotClonePlugin::<SuperFreeze>::default())
+        .add_plugins(GgrsResourceChecksumHashPlugin::<SuperFreeze>::default())
+        .add_plugins(GgrsResourceSnapshotClonePlugin::<KnockOut>::default())
         .insert_resource(ClearColor(Color::rgb(0.05, 0.05, 0.1)))
         .insert_resource(SubstepCount(12)) // default value is 12, lower if desync issues
         .insert_resource(Gravity(Vector::NEG_Y * 1000.0))
-        .insert_resource(PhysicsTimestep::FixedOnce(1. / FPS as f32))
+        .insert_resource(PhysicsTimestep::FixedOnce(1. / settings.fps as f32))
         .init_resource::<FrameCount>()
+        .init_resource::<LastImpact>()
+        .init_resource::<SuperMeter>()
+        .init_resource::<SuperFreeze>()
+        .init_resource::<KnockOut>()
+        .init_resource::<Characters>()
+        .init_resource::<Stages>()
+        .init_resource::<ReplayRecorder>()
         // Some of our systems need the query parameters
         .insert_resource(args)
+        .insert_resource(settings)
         .add_state::<AppState>()
-        .add_systems(Startup, (setup, setup_scene, spawn_characters).chain())
-        .add_systems(Update, log_ggrs_events.run_if(in_state(AppState::InGame)))
+        .add_systems(Startup, setup)
+        .add_systems(
+            OnEnter(AppState::MainMenu),
+            (save_replay, leave_match, close_connection).chain(),
+        )
+        .add_systems(OnEnter(AppState::Lobby), (save_replay, leave_match).chain())
+        .add_systems(
+            OnEnter(AppState::DirectConnect),
+            (save_replay, leave_match).chain(),
+        )
+        .add_systems(
+            OnEnter(AppState::CharacterSelect),
+            (save_replay, leave_match).chain(),
+        )
+        .add_systems(
+            OnEnter(AppState::InGame),
+            (
+                setup_scene,
+                spawn_characters,
+                start_recording,
+                start_local_session.run_if(|mode: Res<GameMode>| !mode.is_online()),
+                start_p2p_session.run_if(|mode: Res<GameMode>| *mode == GameMode::Online),
+                start_spectator_session.run_if(|mode: Res<GameMode>| *mode == GameMode::Spectate),
+                start_direct_session.run_if(|mode: Res<GameMode>| *mode == GameMode::Direct),
+            )
+                .chain()
+                .run_if(
+                    not(resource_exists::<Session<GgrsConfig>>())
+                        .and_then(not(resource_exists::<DelayedSpectatorSession>()))
+                        .and_then(not(resource_exists::<MatchEnded>())),
+                ),
+        )
+        .add_systems(
+            Update,
+            end_replay.run_if(
+                in_state(AppState::InGame)
+                    .and_then(resource_exists::<ReplayPlayback>())
+                    .and_then(replay_finished),
+            ),
+        )
         // these systems will be executed as part of the advance frame update
         .add_systems(
             GgrsSchedule,
@@ -332,59 +644,147 @@ fn main() {
                 //should have those as part of rollback but has issues atm so doing them on startup
                 //setup_scene,
                 //spawn_characters,
-                step_physics,
-                handle_player_input,
-                update_previous_position,
-                increase_frame_system,
+                (record_input_history, tick_super_freeze).chain(),
+                // everything but input and the frame counter holds still during a super freeze
+                (
+                    (
+                        step_physics,
+                        apply_hitstop,
+                        resolve_pushboxes,
+                        limit_fighter_distance,
+                        update_fighter_state,
+                        advance_attacks,
+                    )
+                        .chain(),
+                    (
+                        face_opponent,
+                        start_throws,
+                        update_throws,
+                        update_movement,
+                        update_crouch,
+                        handle_player_input,
+                    )
+                        .chain(),
+                    (
+                        detect_hits,
+                        spawn_projectiles,
+                        move_projectiles,
+                        projectile_hits,
+                        animate_fighters,
+                    )
+                        .chain(),
+                )
+                    .chain()
+                    .run_if(not_super_frozen),
+                (
+                    update_previous_position,
+                    check_knockout,
+                    record_replay,
+                    increase_frame_system,
+                    count_simulated_frames,
+                )
+                    .chain(),
             )
                 .chain(),
         )
         .run();
 }
 
-fn setup(mut commands: Commands, mut app_state: ResMut<NextState<AppState>>, args: Res<Args>) {
+fn setup(mut commands: Commands, mut app_state: ResMut<NextState<AppState>>) {
     commands.spawn((MainCamera, Camera2dBundle::default()));
-    if args.players == 1 {
-        info!("starting synctest session");
-        let mut session_builder = configure_session(1);
+    app_state.set(AppState::MainMenu);
+}
+
+/// Started on entering the game instead of at startup, so the fighters are already there
+/// when the session saves its first frame. Offline modes all run as a synctest session,
+/// with every player on this machine.
+fn start_local_session(
+    mut commands: Commands,
+    args: Res<Args>,
+    mode: Res<GameMode>,
+    settings: Res<Settings>,
+    playback: Option<Res<ReplayPlayback>>,
+) {
+    // a replay plays out the same only at the fps it was recorded at, with every input
+    // on the frame it was recorded on
+    let settings = match &playback {
+        Some(playback) => Settings {
+            fps: playback.0.fps,
+            input_delay: 0,
+            ..*settings
+        },
+        None => *settings,
+    };
+    let players = mode.players(&args);
+    info!("starting synctest session for {mode:?} with {players} player(s)");
+    let mut session_builder = configure_session(&mut commands, players, &settings);
+    for handle in 0..players {
         session_builder = session_builder
-            .add_player(PlayerType::Local, 0)
+            .add_player(PlayerType::Local, handle)
             .expect("failed to add player");
-        let session = session_builder
-            .start_synctest_session()
-            .expect("failed to start synctest session");
-        commands.insert_resource(Session::SyncTest(session));
-        app_state.set(AppState::InGame)
-    } else {
-        info!("joining multiplayer lobby");
-        app_state.set(AppState::Lobby)
     }
+    let session = session_builder
+        .start_synctest_session()
+        .expect("failed to start synctest session");
+    commands.insert_resource(Session::SyncTest(session));
 }
 
-pub fn configure_session(players: usize) -> SessionBuilder<GgrsConfig> {
-    SessionBuilder::<GgrsConfig>::new()
-        .with_num_players(players)
-        .with_max_prediction_window(12)
-        // TODO: re-enable input delay when rollbacks are working properly
-        // .with_input_delay(2)
-        .with_input_delay(0)
-        .with_fps(FPS)
-        .expect("invalid fps")
+/// Run condition for anything that keeps going while the pause menu is open over a match
+pub fn in_match(state: Res<State<AppState>>) -> bool {
+    matches!(state.get(), AppState::InGame | AppState::Paused)
 }
 
-fn log_ggrs_events(mut session: ResMut<Session<GgrsConfig>>) {
-    match session.as_mut() {
-        Session::P2P(s) => {
-            for event in s.events() {
-                info!("GGRS Event: {event:?}");
-                if let GgrsEvent::DesyncDetected { .. } = event {
-                    panic!("desynced!");
-                }
-            }
-        }
-        Session::SyncTest(_) => {}
-        _ => panic!("This example focuses on p2p and synctest"),
+/// Tears down what's left of a match, so the next one starts over from frame 0
+fn leave_match(
+    mut commands: Commands,
+    entities: Query<Entity, Or<(With<Rollback>, With<ParallaxLayer>, With<StageMusic>)>>,
+) {
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
     }
+    commands.remove_resource::<Session<GgrsConfig>>();
+    commands.remove_resource::<PausedSession>();
+    commands.remove_resource::<DelayedSpectatorSession>();
+    commands.remove_resource::<MatchEnded>();
+    commands.insert_resource(FrameCount::default());
+    commands.insert_resource(LastImpact::default());
+    commands.insert_resource(SuperMeter::default());
+    commands.insert_resource(SuperFreeze::default());
+    commands.insert_resource(KnockOut::default());
+    commands.insert_resource(MatchOverConfirmations::default());
+}
+
+/// Drops the connection to the other players and anything being played back, on going back to the menu
+fn close_connection(mut commands: Commands, mut inbox: ResMut<LobbyInbox>) {
+    commands.remove_resource::<MatchboxSocket<MultipleChannels>>();
+    commands.remove_resource::<DirectSocket>();
+    commands.remove_resource::<LanHost>();
+    commands.remove_resource::<GgrsChannel>();
+    commands.remove_resource::<MatchPeers>();
+    commands.remove_resource::<ReplayPlayback>();
+    inbox.0.clear();
+}
+
+/// Session builder set up with `settings`, also matching the physics step to the session's fps
+pub fn configure_session(
+    commands: &mut Commands,
+    players: usize,
+    settings: &Settings,
+) -> SessionBuilder<GgrsConfig> {
+    commands.insert_resource(PhysicsTimestep::FixedOnce(1. / settings.fps as f32));
+    let desync_detection = match settings.desync_interval {
+        0 => DesyncDetection::Off,
+        interval => DesyncDetection::On { interval },
+    };
+    SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(players)
+        .with_max_prediction_window(settings.max_prediction)
+        .with_input_delay(settings.input_delay)
+        .with_disconnect_timeout(Duration::from_millis(settings.disconnect_timeout))
+        .with_disconnect_notify_delay(Duration::from_millis(settings.disconnect_notify_delay))
+        .with_desync_detection_mode(desync_detection)
+        .with_fps(settings.fps)
+        .expect("invalid fps")
 }
 
 fn increase_frame_system(mut frame_count: ResMut<FrameCount>) {
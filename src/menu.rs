@@ -0,0 +1,292 @@
+use crate::{
+    args::Args,
+    character::Characters,
+    direct::DirectSocket,
+    replay::{Replay, ReplayPlayback},
+    room::Room,
+    stage::{Stage, Stages},
+    AppState,
+};
+use bevy::{app::AppExit, prelude::*};
+
+const MAIN_MENU_ENTRIES: [&str; 10] = [
+    "Online Versus",
+    "Private Room",
+    "Spectate",
+    "LAN Games",
+    "Direct Connect",
+    "Local Versus",
+    "Training",
+    "Replays",
+    "Settings",
+    "Quit",
+];
+const ENTRY_COLOR: Color = Color::rgb(0.8, 0.8, 0.8);
+const SELECTED_ENTRY_COLOR: Color = Color::rgb(1.0, 0.8, 0.2);
+/// Background of full screen menus
+pub const MENU_BACKGROUND: Color = Color::rgb(0.1, 0.1, 0.14);
+/// Dims the match behind menus and overlays opened on top of it
+pub const OVERLAY_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+
+/// How the match is played, picked on the main menu
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GameMode {
+    /// Against another peer through the matchbox lobby
+    Online,
+    /// Watching an online match in a room, without playing
+    Spectate,
+    /// Against another player at a known address, without a matchbox server
+    Direct,
+    /// Two players on one keyboard
+    LocalVersus,
+    /// Alone against a dummy that does nothing
+    #[default]
+    Training,
+    /// Watching the last saved match
+    Replay,
+}
+
+impl GameMode {
+    /// Whether the match goes through matchbox instead of being played on this machine
+    pub fn is_online(self) -> bool {
+        matches!(
+            self,
+            GameMode::Online | GameMode::Spectate | GameMode::Direct
+        )
+    }
+
+    /// Players in the GGRS session
+    pub fn players(self, args: &Args) -> usize {
+        match self {
+            GameMode::Online | GameMode::Spectate => args.players,
+            GameMode::Training => 1,
+            GameMode::LocalVersus | GameMode::Replay | GameMode::Direct => 2,
+        }
+    }
+}
+
+/// Highlighted entry of whichever menu is open
+#[derive(Resource, Default)]
+pub struct MenuCursor(pub usize);
+
+/// Text of the menu entry at this index
+#[derive(Component)]
+pub struct MenuEntry(pub usize);
+
+/// Line under the entries for telling the player why something didn't work
+#[derive(Component)]
+pub struct MenuMessage;
+
+/// Marker component for main menu UI
+#[derive(Component)]
+struct MainMenuUI;
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameMode>()
+            .init_resource::<MenuCursor>()
+            .add_systems(OnEnter(AppState::MainMenu), main_menu_startup)
+            .add_systems(
+                Update,
+                (main_menu_input, highlight_menu_entries)
+                    .chain()
+                    .run_if(in_state(AppState::MainMenu)),
+            )
+            .add_systems(OnExit(AppState::MainMenu), despawn_menu::<MainMenuUI>);
+    }
+}
+
+/// Spawns a centered title with a list of entries under it, tagged with `marker`
+pub fn spawn_menu(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    title: &str,
+    entries: &[&str],
+    background: Color,
+    marker: impl Component,
+) {
+    let font = asset_server.load("fonts/quicksand-light.ttf");
+    let text_style = |font_size: f32| TextStyle {
+        font: font.clone(),
+        font_size,
+        color: ENTRY_COLOR,
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(12.0),
+                    ..default()
+                },
+                background_color: background.into(),
+                ..default()
+            },
+            marker,
+        ))
+        .with_children(|parent| {
+            parent.spawn(
+                TextBundle::from_section(title, text_style(72.0)).with_style(Style {
+                    margin: UiRect::bottom(Val::Px(24.0)),
+                    ..default()
+                }),
+            );
+            for (index, entry) in entries.iter().enumerate() {
+                parent.spawn((
+                    TextBundle::from_section(*entry, text_style(40.0)),
+                    MenuEntry(index),
+                ));
+            }
+            parent.spawn((TextBundle::from_section("", text_style(24.0)), MenuMessage));
+        });
+}
+
+/// Moves the cursor with W/S or the arrow keys, returns whether the entry under it was picked
+pub fn navigate_menu(keys: &Input<KeyCode>, cursor: &mut MenuCursor, entries: usize) -> bool {
+    if keys.any_just_pressed([KeyCode::W, KeyCode::Up]) {
+        cursor.0 = (cursor.0 + entries - 1) % entries;
+    }
+    if keys.any_just_pressed([KeyCode::S, KeyCode::Down]) {
+        cursor.0 = (cursor.0 + 1) % entries;
+    }
+    keys.any_just_pressed([KeyCode::Return, KeyCode::U])
+}
+
+pub fn highlight_menu_entries(
+    cursor: Res<MenuCursor>,
+    mut entries: Query<(&MenuEntry, &mut Text)>,
+) {
+    for (entry, mut text) in &mut entries {
+        text.sections[0].style.color = if entry.0 == cursor.0 {
+            SELECTED_ENTRY_COLOR
+        } else {
+            ENTRY_COLOR
+        };
+    }
+}
+
+pub fn despawn_menu<T: Component>(query: Query<Entity, With<T>>, mut commands: Commands) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn main_menu_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut cursor: ResMut<MenuCursor>,
+) {
+    cursor.0 = 0;
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        "Box Fighter",
+        &MAIN_MENU_ENTRIES,
+        MENU_BACKGROUND,
+        MainMenuUI,
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+fn main_menu_input(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    args: Res<Args>,
+    stages: Res<Stages>,
+    characters: Res<Characters>,
+    mut cursor: ResMut<MenuCursor>,
+    mut mode: ResMut<GameMode>,
+    mut app_state: ResMut<NextState<AppState>>,
+    mut app_exit: EventWriter<AppExit>,
+    mut message: Query<&mut Text, With<MenuMessage>>,
+) {
+    if !navigate_menu(&keys, &mut cursor, MAIN_MENU_ENTRIES.len()) {
+        return;
+    }
+
+    // offline matches are played on the stage from the command line, or the first one
+    let offline_stage = || {
+        let stage = stages.index_or_default(args.stage.as_deref());
+        Stage(stages.0[stage].clone())
+    };
+
+    match MAIN_MENU_ENTRIES[cursor.0] {
+        "Online Versus" => {
+            *mode = GameMode::Online;
+            commands.insert_resource(Room::from_args(&args));
+            app_state.set(AppState::Lobby);
+        }
+        "Private Room" => app_state.set(AppState::RoomCode),
+        // matchmaking without a room would pair the spectator up as a player
+        "Spectate" if args.room.is_none() => {
+            message.single_mut().sections[0].value =
+                "Spectating needs the room of the match, start with --room or enter its code under Private Room"
+                    .to_string();
+        }
+        "Spectate" => {
+            *mode = GameMode::Spectate;
+            commands.insert_resource(Room::from_args(&args));
+            app_state.set(AppState::Lobby);
+        }
+        "LAN Games" => app_state.set(AppState::LanBrowser),
+        "Direct Connect" => {
+            let Some(peer) = args.connect else {
+                message.single_mut().sections[0].value =
+                    "Direct connect needs the other player's address, start with --connect ip:port"
+                        .to_string();
+                return;
+            };
+            match DirectSocket::bind(args.local_port, peer) {
+                Ok(socket) => {
+                    *mode = GameMode::Direct;
+                    commands.insert_resource(socket);
+                    app_state.set(AppState::DirectConnect);
+                }
+                Err(err) => {
+                    warn!("can't bind port {}: {err}", args.local_port);
+                    message.single_mut().sections[0].value =
+                        format!("Can't use port {}: {err}", args.local_port);
+                }
+            }
+        }
+        "Local Versus" => {
+            *mode = GameMode::LocalVersus;
+            commands.insert_resource(offline_stage());
+            app_state.set(AppState::CharacterSelect);
+        }
+        "Training" => {
+            *mode = GameMode::Training;
+            commands.insert_resource(offline_stage());
+            app_state.set(AppState::CharacterSelect);
+        }
+        "Replays" => {
+            let replay = Replay::load().and_then(|replay| {
+                let (stage, selections) = replay.resolve(&stages, &characters)?;
+                Ok((replay, stage, selections))
+            });
+            match replay {
+                Ok((replay, stage, selections)) => {
+                    *mode = GameMode::Replay;
+                    commands.insert_resource(stage);
+                    commands.insert_resource(selections);
+                    commands.insert_resource(ReplayPlayback(replay));
+                    app_state.set(AppState::InGame);
+                }
+                Err(err) => {
+                    warn!("can't play the last replay: {err}");
+                    message.single_mut().sections[0].value = format!("No replay to watch: {err}");
+                }
+            }
+        }
+        "Settings" => app_state.set(AppState::Settings),
+        _ => app_exit.send(AppExit),
+    }
+}
@@ -0,0 +1,59 @@
+use crate::PLAYERS;
+use bevy::prelude::*;
+
+pub const MAX_METER: u32 = 1000;
+
+/// Super meter of every player, indexed by player handle
+#[derive(Resource, Clone, Copy, Debug, Default, Hash)]
+pub struct SuperMeter {
+    meters: [u32; PLAYERS],
+}
+
+impl SuperMeter {
+    pub fn get(&self, handle: usize) -> u32 {
+        self.meters.get(handle).copied().unwrap_or(0)
+    }
+
+    pub fn gain(&mut self, handle: usize, amount: u32) {
+        if let Some(meter) = self.meters.get_mut(handle) {
+            *meter = (*meter + amount).min(MAX_METER);
+        }
+    }
+
+    /// Takes `amount` out of the meter, fails without spending anything if there isn't enough
+    pub fn spend(&mut self, handle: usize, amount: u32) -> bool {
+        match self.meters.get_mut(handle) {
+            Some(meter) if *meter >= amount => {
+                *meter -= amount;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The attacker builds meter from dealing damage (half as much when blocked),
+    /// the defender gets half of the damage taken
+    pub fn on_hit(&mut self, attacker: usize, defender: usize, damage: u32, blocked: bool) {
+        if blocked {
+            self.gain(attacker, damage / 2);
+        } else {
+            self.gain(attacker, damage);
+            self.gain(defender, damage / 2);
+        }
+    }
+}
+
+/// Cinematic pause when a super starts, the whole simulation holds still until it runs out
+#[derive(Resource, Clone, Copy, Debug, Default, Hash)]
+pub struct SuperFreeze {
+    pub frames: u32,
+}
+
+pub fn tick_super_freeze(mut super_freeze: ResMut<SuperFreeze>) {
+    super_freeze.frames = super_freeze.frames.saturating_sub(1);
+}
+
+/// Run condition for everything that should stop during a super freeze
+pub fn not_super_frozen(super_freeze: Res<SuperFreeze>) -> bool {
+    super_freeze.frames == 0
+}
@@ -0,0 +1,259 @@
+use crate::{
+    character::{CharacterId, Characters},
+    combat::{AttackState, FighterState, Hitstop, Hurtbox, Invulnerable},
+    input::*,
+    stage::Stage,
+    Player,
+};
+use bevy::prelude::*;
+use bevy_xpbd_2d::{math::*, prelude::*};
+
+/// Movement option a fighter is in the middle of. Kept apart from `FighterState`
+/// since attacks can still be started out of a dash or run, which cancels it.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component, Hash)]
+pub enum Movement {
+    #[default]
+    Neutral,
+    Dash {
+        frames: u32,
+    },
+    /// Forward held after a dash keeps the fighter running until it's let go
+    Run,
+    Backdash {
+        frames: u32,
+    },
+    AirDash {
+        frames: u32,
+        forward: bool,
+    },
+}
+
+/// Whether the fighter is crouching, which shrinks its hurtbox and lets it block lows
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component, Hash)]
+pub struct Crouching(pub bool);
+
+/// Only one air dash is allowed per jump
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct AirDashUsed(pub bool);
+
+pub fn is_grounded(position: Vector, stage: &Stage) -> bool {
+    position.y <= stage.ground_y() + 1.0
+}
+
+/// Keeps grounded fighters that are free to act turned towards each other
+pub fn face_opponent(
+    stage: Res<Stage>,
+    mut query: Query<
+        (
+            Entity,
+            &Position,
+            &FighterState,
+            &Movement,
+            &AttackState,
+            &mut TextureAtlasSprite,
+        ),
+        With<Player>,
+    >,
+) {
+    let positions: Vec<(Entity, Scalar)> = query
+        .iter()
+        .map(|(entity, position, ..)| (entity, position.0.x))
+        .collect();
+
+    for (entity, position, state, movement, attack, mut sprite) in &mut query {
+        if *state != FighterState::Idle
+            || *movement != Movement::Neutral
+            || attack.attacking()
+            || !is_grounded(position.0, &stage)
+        {
+            continue;
+        }
+        let Some(&(_, opponent_x)) = positions.iter().find(|(other, _)| *other != entity) else {
+            continue;
+        };
+        if opponent_x != position.0.x {
+            sprite.flip_x = opponent_x < position.0.x;
+        }
+    }
+}
+
+/// Starts and plays out dashes, decided purely from the input history
+/// and character data so rollbacks replay them exactly.
+pub fn update_movement(
+    characters: Res<Characters>,
+    stage: Res<Stage>,
+    mut query: Query<
+        (
+            &CharacterId,
+            &InputHistory,
+            &FighterState,
+            &Hitstop,
+            &AttackState,
+            &TextureAtlasSprite,
+            &Position,
+            &mut LinearVelocity,
+            &mut Movement,
+            &mut AirDashUsed,
+            &mut Invulnerable,
+        ),
+        With<Player>,
+    >,
+) {
+    for (
+        character,
+        history,
+        state,
+        hitstop,
+        attack,
+        sprite,
+        position,
+        mut velocity,
+        mut movement,
+        mut air_dash_used,
+        mut invulnerable,
+    ) in &mut query
+    {
+        if hitstop.active() {
+            continue;
+        }
+        let grounded = is_grounded(position.0, &stage);
+        if grounded {
+            air_dash_used.0 = false;
+        }
+
+        // getting hit or grabbed cancels a dash, and so does starting an attack out of one
+        if *state != FighterState::Idle || attack.attacking() {
+            *movement = Movement::Neutral;
+            continue;
+        }
+
+        let dash = &characters.get(character).dash;
+        let facing = if sprite.flip_x { -1.0 } else { 1.0 };
+        let (forward, forward_tap, back_tap) = if sprite.flip_x {
+            (
+                INPUT_LEFT,
+                INPUT_LEFT_JUST_PRESSED,
+                INPUT_RIGHT_JUST_PRESSED,
+            )
+        } else {
+            (
+                INPUT_RIGHT,
+                INPUT_RIGHT_JUST_PRESSED,
+                INPUT_LEFT_JUST_PRESSED,
+            )
+        };
+        let buttons = history.get(0);
+        let double_tap =
+            |tap: u16| buttons & tap != 0 && history.any_within(tap, 1, dash.double_tap_window);
+
+        *movement = match *movement {
+            Movement::Dash { frames } if frames > 1 => Movement::Dash { frames: frames - 1 },
+            Movement::Backdash { frames } if frames > 1 => {
+                Movement::Backdash { frames: frames - 1 }
+            }
+            Movement::AirDash { frames, forward } if frames > 1 => Movement::AirDash {
+                frames: frames - 1,
+                forward,
+            },
+            Movement::Dash { .. } | Movement::Run if grounded && buttons & forward != 0 => {
+                Movement::Run
+            }
+            _ if grounded && double_tap(forward_tap) => Movement::Dash {
+                frames: dash.dash_frames,
+            },
+            _ if grounded && double_tap(back_tap) => {
+                invulnerable.frames = dash.backdash_invuln;
+                Movement::Backdash {
+                    frames: dash.backdash_frames,
+                }
+            }
+            _ if !grounded
+                && !air_dash_used.0
+                && (double_tap(forward_tap) || double_tap(back_tap)) =>
+            {
+                air_dash_used.0 = true;
+                Movement::AirDash {
+                    frames: dash.air_dash_frames,
+                    forward: double_tap(forward_tap),
+                }
+            }
+            _ => Movement::Neutral,
+        };
+
+        match *movement {
+            Movement::Neutral => {}
+            Movement::Dash { .. } => velocity.x = facing * dash.dash_speed,
+            Movement::Run => velocity.x = facing * dash.run_speed,
+            Movement::Backdash { .. } => velocity.x = -facing * dash.backdash_speed,
+            Movement::AirDash { forward, .. } => {
+                let direction = if forward { facing } else { -facing };
+                // air dashes hold their height instead of falling
+                velocity.0 = Vector::new(direction * dash.air_dash_speed, 0.0);
+            }
+        }
+    }
+}
+
+/// Puts fighters holding down into a crouch, swapping in the shorter hurtbox
+pub fn update_crouch(
+    characters: Res<Characters>,
+    stage: Res<Stage>,
+    mut query: Query<
+        (
+            &CharacterId,
+            &InputHistory,
+            &FighterState,
+            &Hitstop,
+            &AttackState,
+            &Movement,
+            &Position,
+            &mut Crouching,
+            &mut Hurtbox,
+            &mut LinearVelocity,
+        ),
+        With<Player>,
+    >,
+) {
+    for (
+        character,
+        history,
+        state,
+        hitstop,
+        attack,
+        movement,
+        position,
+        mut crouching,
+        mut hurtbox,
+        mut velocity,
+    ) in &mut query
+    {
+        if hitstop.active() {
+            continue;
+        }
+        let holding_down = history.get(0) & INPUT_DOWN != 0;
+
+        crouching.0 = match *state {
+            // attacks and hitstun keep whatever stance they started in
+            FighterState::Idle if attack.attacking() => crouching.0,
+            FighterState::Hitstun { .. } => crouching.0,
+            FighterState::Idle | FighterState::Blockstun { .. } => {
+                holding_down && is_grounded(position.0, &stage) && *movement == Movement::Neutral
+            }
+            _ => false,
+        };
+
+        let character = characters.get(character);
+        *hurtbox = if crouching.0 {
+            character.crouching_hurtbox
+        } else {
+            character.standing_hurtbox
+        };
+
+        // crouching fighters stay put instead of walking
+        if crouching.0 && *state == FighterState::Idle {
+            velocity.x = 0.0;
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{
+    in_match,
+    menu::{despawn_menu, OVERLAY_COLOR},
+    AppState, FrameCount, GgrsConfig,
+};
+use bevy::{
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+use bevy_ggrs::Session;
+
+/// How often the rollback count gets turned into a per second rate
+const SAMPLE_SECONDS: f32 = 1.0;
+
+/// Frames the rollback schedule simulated, resimulated ones included.
+/// Kept out of the rollback state so rollbacks can't take it back.
+#[derive(Resource, Default)]
+pub struct SimulatedFrames(usize);
+
+pub fn count_simulated_frames(mut simulated: ResMut<SimulatedFrames>) {
+    simulated.0 += 1;
+}
+
+/// Toggled with F3, shows how the netcode is doing for debugging bad sessions
+#[derive(Resource, Default)]
+struct NetStats {
+    visible: bool,
+    /// When the current sample started, and the simulated frames and frame count back then
+    sample: (f32, usize, usize),
+    /// Frames simulated again because of rollbacks during the last full sample
+    rollback_frames_per_second: f32,
+}
+
+/// Marker component for the stats overlay
+#[derive(Component)]
+struct NetStatsUI;
+
+#[derive(Component)]
+struct NetStatsText;
+
+pub struct NetStatsPlugin;
+
+impl Plugin for NetStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulatedFrames>()
+            .init_resource::<NetStats>()
+            .add_systems(
+                OnEnter(AppState::InGame),
+                net_stats_startup.run_if(not(any_with_component::<NetStatsUI>())),
+            )
+            .add_systems(
+                Update,
+                (toggle_net_stats, sample_rollbacks, update_net_stats)
+                    .chain()
+                    .run_if(in_match),
+            );
+        for state in [
+            AppState::MainMenu,
+            AppState::Lobby,
+            AppState::DirectConnect,
+            AppState::CharacterSelect,
+        ] {
+            app.add_systems(OnEnter(state), despawn_menu::<NetStatsUI>);
+        }
+    }
+}
+
+fn net_stats_startup(mut commands: Commands, asset_server: Res<AssetServer>, stats: Res<NetStats>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(16.0),
+                    bottom: Val::Px(16.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                },
+                background_color: OVERLAY_COLOR.into(),
+                visibility: if stats.visible {
+                    Visibility::Inherited
+                } else {
+                    Visibility::Hidden
+                },
+                ..default()
+            },
+            NetStatsUI,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/quicksand-light.ttf"),
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                NetStatsText,
+            ));
+        });
+}
+
+fn toggle_net_stats(
+    keys: Res<Input<KeyCode>>,
+    mut stats: ResMut<NetStats>,
+    mut overlay: Query<&mut Visibility, With<NetStatsUI>>,
+) {
+    if !keys.just_pressed(KeyCode::F3) {
+        return;
+    }
+    stats.visible = !stats.visible;
+    for mut visibility in &mut overlay {
+        *visibility = if stats.visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+/// Every frame simulated past the ones the frame count moved forward by was a resimulation
+fn sample_rollbacks(
+    time: Res<Time>,
+    frame_count: Res<FrameCount>,
+    simulated: Res<SimulatedFrames>,
+    mut stats: ResMut<NetStats>,
+) {
+    let now = time.elapsed_seconds();
+    let (started, simulated_then, frame_then) = stats.sample;
+    let elapsed = now - started;
+    if elapsed < SAMPLE_SECONDS {
+        return;
+    }
+    // a new match starts the frame count over, that sample can't be trusted
+    if **frame_count >= frame_then && simulated.0 >= simulated_then {
+        let advanced = **frame_count - frame_then;
+        let resimulated = (simulated.0 - simulated_then).saturating_sub(advanced);
+        stats.rollback_frames_per_second = resimulated as f32 / elapsed;
+    }
+    stats.sample = (now, simulated.0, **frame_count);
+}
+
+fn update_net_stats(
+    stats: Res<NetStats>,
+    diagnostics: Res<DiagnosticsStore>,
+    session: Option<Res<Session<GgrsConfig>>>,
+    mut text: Query<&mut Text, With<NetStatsText>>,
+) {
+    if !stats.visible {
+        return;
+    }
+    let Ok(mut text) = text.get_single_mut() else {
+        return;
+    };
+
+    let fps = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.smoothed())
+        .unwrap_or_default();
+    let mut lines = vec![
+        format!("FPS: {fps:.0}"),
+        format!("Rollback frames/s: {:.1}", stats.rollback_frames_per_second),
+    ];
+
+    if let Some(Session::Spectator(session)) = session.as_deref() {
+        lines.push(format!(
+            "Frames behind host: {}",
+            session.frames_behind_host()
+        ));
+    }
+    if let Some(Session::P2P(session)) = session.as_deref() {
+        // how many frames have been simulated on predicted inputs
+        let prediction = session.current_frame() - session.confirmed_frame();
+        lines.push(format!("Prediction depth: {}", prediction.max(0)));
+        for handle in session.remote_player_handles() {
+            match session.network_stats(handle) {
+                Ok(network) => lines.push(format!(
+                    "P{}: ping {}ms, send queue {}, frame advantage {} local / {} remote",
+                    handle + 1,
+                    network.ping,
+                    network.send_queue_len,
+                    -network.local_frames_behind,
+                    -network.remote_frames_behind,
+                )),
+                // stats only show up once the session has been running for a moment
+                Err(_) => lines.push(format!("P{}: no stats yet", handle + 1)),
+            }
+        }
+    }
+
+    text.sections[0].value = lines.join("\n");
+}
@@ -0,0 +1,153 @@
+use crate::{
+    connection::MatchEnded,
+    leave_match,
+    menu::{
+        despawn_menu, highlight_menu_entries, navigate_menu, spawn_menu, GameMode, MenuCursor,
+        OVERLAY_COLOR,
+    },
+    replay::save_replay,
+    AppState, GgrsConfig,
+};
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+
+/// Session taken out of the world while an offline match is paused.
+/// Without a session bevy_ggrs has nothing to advance, so the `GgrsSchedule` stops running.
+#[derive(Resource)]
+pub struct PausedSession(pub Session<GgrsConfig>);
+
+/// Set when restart round was picked, the match gets torn down on leaving the pause menu
+#[derive(Resource)]
+struct RestartRound;
+
+/// Entries of the open pause menu, which depend on the game mode
+#[derive(Resource)]
+struct PauseMenu(Vec<&'static str>);
+
+/// Marker component for pause menu UI
+#[derive(Component)]
+struct PauseUI;
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            // a match that ended early has nothing left to pause, it's about to be left
+            open_pause_menu
+                .run_if(in_state(AppState::InGame).and_then(not(resource_exists::<MatchEnded>()))),
+        )
+        .add_systems(
+            OnEnter(AppState::Paused),
+            (
+                halt_session.run_if(|mode: Res<GameMode>| !mode.is_online()),
+                pause_startup,
+            ),
+        )
+        .add_systems(
+            Update,
+            (pause_input, highlight_menu_entries)
+                .chain()
+                .run_if(in_state(AppState::Paused)),
+        )
+        .add_systems(
+            OnExit(AppState::Paused),
+            (
+                despawn_menu::<PauseUI>,
+                (save_replay, leave_match).run_if(resource_exists::<RestartRound>()),
+                resume_session,
+            )
+                .chain(),
+        );
+    }
+}
+
+fn open_pause_menu(keys: Res<Input<KeyCode>>, mut app_state: ResMut<NextState<AppState>>) {
+    if keys.just_pressed(KeyCode::Escape) {
+        app_state.set(AppState::Paused);
+    }
+}
+
+fn halt_session(world: &mut World) {
+    if let Some(session) = world.remove_resource::<Session<GgrsConfig>>() {
+        info!("pausing the match");
+        world.insert_resource(PausedSession(session));
+    }
+}
+
+fn resume_session(world: &mut World) {
+    // a restarted round starts a fresh session on entering the game
+    if world.remove_resource::<RestartRound>().is_some() {
+        return;
+    }
+    if let Some(PausedSession(session)) = world.remove_resource::<PausedSession>() {
+        info!("resuming the match");
+        world.insert_resource(session);
+    }
+}
+
+fn pause_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mode: Res<GameMode>,
+    mut cursor: ResMut<MenuCursor>,
+) {
+    // online the other player keeps playing, so there's nothing to restart or leave to here
+    let entries = match *mode {
+        GameMode::Online | GameMode::Direct => vec!["Resume"],
+        // a spectator leaving doesn't bother anyone
+        GameMode::Spectate => vec!["Resume", "Quit to Menu"],
+        GameMode::Replay => vec!["Resume", "Restart Round", "Quit to Menu"],
+        GameMode::Training | GameMode::LocalVersus => {
+            vec![
+                "Resume",
+                "Restart Round",
+                "Character Select",
+                "Quit to Menu",
+            ]
+        }
+    };
+    let title = if mode.is_online() {
+        "Menu (match still running)"
+    } else {
+        "Paused"
+    };
+
+    cursor.0 = 0;
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        title,
+        &entries,
+        OVERLAY_COLOR,
+        PauseUI,
+    );
+    commands.insert_resource(PauseMenu(entries));
+}
+
+fn pause_input(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    menu: Res<PauseMenu>,
+    mut cursor: ResMut<MenuCursor>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        app_state.set(AppState::InGame);
+        return;
+    }
+    if !navigate_menu(&keys, &mut cursor, menu.0.len()) {
+        return;
+    }
+
+    match menu.0[cursor.0] {
+        "Resume" => app_state.set(AppState::InGame),
+        "Restart Round" => {
+            commands.insert_resource(RestartRound);
+            app_state.set(AppState::InGame);
+        }
+        "Character Select" => app_state.set(AppState::CharacterSelect),
+        _ => app_state.set(AppState::MainMenu),
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{
+    character::{CharacterId, Characters},
+    combat::*,
+    in_match,
+    meter::SuperMeter,
+    FrameCount, GgrsConfig, Player,
+};
+use bevy::prelude::*;
+use bevy_ggrs::{prelude::*, GgrsComponentSnapshotClonePlugin};
+use bevy_xpbd_2d::prelude::*;
+use serde::Deserialize;
+
+/// How many projectiles a single player can have on screen at once
+const MAX_PROJECTILES_PER_PLAYER: usize = 1;
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct ProjectileData {
+    /// Units moved per frame
+    pub speed: f32,
+    /// Frames until it fizzles out on its own
+    pub lifetime: u32,
+    /// How many other projectiles it can clash with before it's destroyed
+    pub durability: u32,
+    pub spawn_offset: Vec2,
+    pub hitbox: HitboxData,
+}
+
+/// Projectiles are spawned inside the rollback schedule so they only carry simulation state,
+/// the sprite gets attached afterwards by `attach_projectile_sprites`.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Projectile {
+    /// Handle of the player that threw it
+    pub owner: usize,
+    pub velocity: Vec2,
+    pub lifetime: u32,
+    pub durability: u32,
+    pub hitbox: HitboxData,
+}
+
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(GgrsComponentSnapshotClonePlugin::<Projectile>::default())
+            .add_systems(Update, attach_projectile_sprites.run_if(in_match));
+    }
+}
+
+/// Throws the projectile of any move that just reached its first active frame
+pub fn spawn_projectiles(
+    mut commands: Commands,
+    characters: Res<Characters>,
+    players: Query<(
+        &Player,
+        &CharacterId,
+        &Position,
+        &TextureAtlasSprite,
+        &Hitstop,
+        &FighterState,
+        &AttackState,
+    )>,
+    projectiles: Query<&Projectile>,
+) {
+    for (player, character, position, sprite, hitstop, state, attack) in &players {
+        let Some(move_index) = attack.current else {
+            continue;
+        };
+        if hitstop.active() || *state != FighterState::Idle {
+            continue;
+        }
+        let attack_move = &characters.get(character).moves[move_index];
+        let Some(data) = attack_move.projectile else {
+            continue;
+        };
+        if attack.frame != attack_move.startup {
+            continue;
+        }
+
+        let active = projectiles
+            .iter()
+            .filter(|projectile| projectile.owner == player.handle)
+            .count();
+        if active >= MAX_PROJECTILES_PER_PLAYER {
+            continue;
+        }
+
+        let facing = if sprite.flip_x { -1.0 } else { 1.0 };
+        let spawn_at = position.0 + Vec2::new(data.spawn_offset.x * facing, data.spawn_offset.y);
+
+        commands
+            .spawn((
+                Projectile {
+                    owner: player.handle,
+                    velocity: Vec2::new(data.speed * facing, 0.0),
+                    lifetime: data.lifetime,
+                    durability: data.durability,
+                    hitbox: data.hitbox,
+                },
+                Transform::from_translation(spawn_at.extend(1.0)),
+            ))
+            .add_rollback();
+    }
+}
+
+pub fn move_projectiles(
+    mut commands: Commands,
+    mut projectiles: Query<(Entity, &mut Projectile, &mut Transform)>,
+) {
+    for (entity, mut projectile, mut transform) in &mut projectiles {
+        if projectile.lifetime == 0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        projectile.lifetime -= 1;
+        transform.translation += projectile.velocity.extend(0.0);
+    }
+}
+
+pub fn projectile_hits(
+    mut commands: Commands,
+    frame_count: Res<FrameCount>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut last_impact: ResMut<LastImpact>,
+    mut meter: ResMut<SuperMeter>,
+    mut projectiles: Query<(Entity, &mut Projectile, &Transform)>,
+    mut fighters: Query<(
+        &Player,
+        &Position,
+        &LinearVelocity,
+        &Hurtbox,
+        &Invulnerable,
+        &mut Health,
+        &mut Hitstop,
+        &mut FighterState,
+        &mut AttackState,
+    )>,
+) {
+    let boxes: Vec<(Entity, usize, Aabb)> = projectiles
+        .iter()
+        .filter(|(_, projectile, _)| projectile.durability > 0 && projectile.lifetime > 0)
+        .map(|(entity, projectile, transform)| {
+            let aabb = Aabb {
+                center: transform.translation.truncate(),
+                size: projectile.hitbox.size,
+            };
+            (entity, projectile.owner, aabb)
+        })
+        .collect();
+
+    // projectiles from different players cancel each other out
+    for (i, (entity, owner, aabb)) in boxes.iter().enumerate() {
+        for (other, other_owner, other_aabb) in &boxes[i + 1..] {
+            if owner == other_owner || !aabb.overlaps(other_aabb) {
+                continue;
+            }
+            for clashed in [*entity, *other] {
+                if let Ok((_, mut projectile, _)) = projectiles.get_mut(clashed) {
+                    projectile.durability = projectile.durability.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    for (entity, mut projectile, transform) in &mut projectiles {
+        if projectile.lifetime == 0 {
+            continue;
+        }
+        if projectile.durability == 0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let aabb = Aabb {
+            center: transform.translation.truncate(),
+            size: projectile.hitbox.size,
+        };
+        let hitbox = projectile.hitbox;
+        for (
+            player,
+            position,
+            velocity,
+            hurtbox,
+            invulnerable,
+            mut health,
+            mut hitstop,
+            mut state,
+            mut attack,
+        ) in &mut fighters
+        {
+            if player.handle == projectile.owner
+                || invulnerable.active()
+                || state.knocked_down()
+                || !aabb.overlaps(&hurtbox.aabb(position.0))
+            {
+                continue;
+            }
+
+            let buttons = inputs.get(player.handle).map_or(0, |input| input.0.buttons);
+            let blocked =
+                can_block(&state, &attack) && holding_back(buttons, position.0.x, aabb.center.x);
+            land_hit(&hitbox, blocked, &mut health, &mut state, &mut attack);
+            meter.on_hit(projectile.owner, player.handle, hitbox.damage, blocked);
+
+            // only the victim freezes, the thrower is free to keep moving
+            *hitstop = Hitstop {
+                frames: hitbox.hitstop,
+                position: position.0,
+                velocity: velocity.0,
+            };
+            *last_impact = LastImpact {
+                frame: Some(**frame_count),
+                hitstop: hitbox.hitstop,
+            };
+
+            projectile.durability = 0;
+            commands.entity(entity).despawn();
+            break;
+        }
+    }
+}
+
+/// Gives newly spawned (or rollback restored) projectiles something to draw
+fn attach_projectile_sprites(
+    mut commands: Commands,
+    projectiles: Query<(Entity, &Projectile, &Transform), Without<Sprite>>,
+) {
+    for (entity, projectile, transform) in &projectiles {
+        commands.entity(entity).insert(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(1.0, 0.55, 0.1),
+                custom_size: Some(projectile.hitbox.size),
+                ..default()
+            },
+            transform: *transform,
+            ..default()
+        });
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    camera::max_fighter_distance,
+    character::{CharacterId, Characters},
+    combat::{Aabb, FighterState},
+    stage::Stage,
+    Player, PrevPos,
+};
+use bevy::prelude::*;
+use bevy_xpbd_2d::{math::*, prelude::*};
+use serde::Deserialize;
+
+/// Physics layers, fighters only collide with the stage through xpbd
+/// and are kept apart from each other by their pushboxes instead
+#[derive(PhysicsLayer)]
+pub enum Layer {
+    World,
+    Fighter,
+}
+
+/// Body of a fighter that the other fighter can't overlap, offset from its position
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct Pushbox {
+    pub offset: Vec2,
+    pub size: Vec2,
+}
+
+impl Pushbox {
+    pub fn aabb(&self, position: Vector) -> Aabb {
+        Aabb {
+            center: position + self.offset,
+            size: self.size,
+        }
+    }
+}
+
+/// Pushes overlapping fighters apart horizontally only, so nobody can stand on a head.
+/// The overlap is split between both fighters, unless one is already in the corner
+/// in which case the other one takes all of it.
+/// Fighters end up on the side their center is on, so a jump that carries a fighter
+/// past the other's center crosses up, anything short of that gets pushed back.
+pub fn resolve_pushboxes(
+    characters: Res<Characters>,
+    stage: Res<Stage>,
+    mut fighters: Query<(
+        &Player,
+        &CharacterId,
+        &FighterState,
+        &PrevPos,
+        &mut Position,
+    )>,
+) {
+    let mut boxes: Vec<(usize, Pushbox, Vector, Vector)> = fighters
+        .iter()
+        .filter(|(_, _, state, ..)| {
+            // throws place both fighters themselves
+            !matches!(
+                state,
+                FighterState::Throwing { .. } | FighterState::Thrown { .. }
+            )
+        })
+        .map(|(player, character, _, previous, position)| {
+            let pushbox = characters.get(character).pushbox;
+            (player.handle, pushbox, position.0, previous.0)
+        })
+        .collect();
+    // iterate in handle order so every peer resolves the same way
+    boxes.sort_by_key(|(handle, ..)| *handle);
+
+    let [(a, a_box, a_position, a_previous), (b, b_box, b_position, b_previous)] = boxes[..] else {
+        return;
+    };
+    let a_aabb = a_box.aabb(a_position);
+    let b_aabb = b_box.aabb(b_position);
+    if !a_aabb.overlaps(&b_aabb) {
+        return;
+    }
+
+    // which way a gets pushed, ties go to whoever was on that side last frame
+    let a_left = if a_aabb.center.x != b_aabb.center.x {
+        a_aabb.center.x < b_aabb.center.x
+    } else if a_previous.x != b_previous.x {
+        a_previous.x < b_previous.x
+    } else {
+        a < b
+    };
+    let direction = if a_left { -1.0 } else { 1.0 };
+    let overlap = (a_aabb.size.x + b_aabb.size.x) * 0.5 - (a_aabb.center.x - b_aabb.center.x).abs();
+
+    // how far each fighter can still move away from the other before hitting a wall
+    let room = |aabb: &Aabb, direction: Scalar| {
+        let edge = aabb.center.x + direction * aabb.size.x * 0.5;
+        (stage.walls - edge * direction).max(0.0)
+    };
+    let a_push = (overlap * 0.5).min(room(&a_aabb, direction));
+    let b_push = (overlap - a_push).min(room(&b_aabb, -direction));
+    // b might be cornered too, a takes whatever is left
+    let a_push = (overlap - b_push).min(room(&a_aabb, direction));
+
+    for (player, .., mut position) in &mut fighters {
+        if player.handle == a {
+            position.x += direction * a_push;
+        } else if player.handle == b {
+            position.x -= direction * b_push;
+        }
+    }
+}
+
+/// Stops the fighters from getting further apart than the camera can show.
+/// Whoever moved away since last frame gets held back first, so walking away from
+/// the opponent stops at the edge of the screen instead of dragging them along.
+pub fn limit_fighter_distance(
+    stage: Res<Stage>,
+    mut fighters: Query<(&Player, &PrevPos, &mut Position)>,
+) {
+    let mut positions: Vec<(usize, Scalar, Scalar)> = fighters
+        .iter()
+        .map(|(player, previous, position)| (player.handle, position.x, previous.0.x))
+        .collect();
+    positions.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    let [(left, left_x, left_previous), (right, right_x, right_previous)] = positions[..] else {
+        return;
+    };
+    let mut excess = right_x - left_x - max_fighter_distance(&stage);
+    if excess <= 0.0 {
+        return;
+    }
+
+    let left_pull = (left_previous - left_x).clamp(0.0, excess);
+    excess -= left_pull;
+    let right_pull = (right_x - right_previous).clamp(0.0, excess);
+    excess -= right_pull;
+
+    for (player, _, mut position) in &mut fighters {
+        if player.handle == left {
+            position.x += left_pull + excess * 0.5;
+        } else if player.handle == right {
+            position.x -= right_pull + excess * 0.5;
+        }
+    }
+}
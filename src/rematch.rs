@@ -0,0 +1,348 @@
+use crate::{
+    character_select::watch_peers,
+    combat::{Health, KnockOut},
+    direct::DirectSocket,
+    in_match, leave_match,
+    lobby::{LobbyInbox, LobbyMessage, MatchPeers},
+    menu::{
+        despawn_menu, highlight_menu_entries, navigate_menu, spawn_menu, GameMode, MenuCursor,
+        MenuEntry, MenuMessage, OVERLAY_COLOR,
+    },
+    replay::save_replay,
+    stage::{Stage, Stages},
+    AppState, GgrsConfig, Player,
+};
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+use bevy_matchbox::prelude::*;
+
+/// What a player wants the next match to be
+#[derive(Clone, Copy, Debug)]
+struct RematchVote {
+    stage: usize,
+    /// Going through character select again instead of keeping the picks
+    reselect: bool,
+}
+
+/// The menu after a match, and everyone's choice for the next one
+#[derive(Resource)]
+struct RematchMenu {
+    entries: Vec<&'static str>,
+    /// Stage shown on the stage entry
+    stage: usize,
+    local_vote: Option<RematchVote>,
+    /// Votes of the other players
+    votes: Vec<(PeerId, RematchVote)>,
+}
+
+/// Players known to have confirmed the knockout, while the session is kept up for the others.
+/// Dropping it as soon as the local side confirmed could leave another player without the last
+/// inputs, timing out on a match that's over.
+#[derive(Resource, Default)]
+pub struct MatchOverConfirmations {
+    local: bool,
+    remote: Vec<PeerId>,
+}
+
+/// Marker component for rematch menu UI
+#[derive(Component)]
+struct RematchUI;
+
+pub struct RematchPlugin;
+
+impl Plugin for RematchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MatchOverConfirmations>()
+            .add_systems(
+                Update,
+                end_match.run_if(in_match.and_then(resource_exists::<Session<GgrsConfig>>())),
+            )
+            .add_systems(OnEnter(AppState::MatchOver), rematch_startup)
+            .add_systems(
+                Update,
+                (
+                    watch_peers.run_if(resource_exists::<MatchboxSocket<MultipleChannels>>()),
+                    receive_rematch_votes.run_if(resource_exists::<MatchPeers>()),
+                    rematch_input,
+                    update_rematch_entries,
+                    highlight_menu_entries,
+                    start_rematch,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::MatchOver)),
+            )
+            // the finished match stays on screen behind the menu until it's left
+            .add_systems(
+                OnExit(AppState::MatchOver),
+                (despawn_menu::<RematchUI>, save_replay, leave_match).chain(),
+            );
+    }
+}
+
+/// Ends the match once the knockout is confirmed and can't be rolled back anymore, by every
+/// player. Until then the session keeps running, so the others still get the inputs they need.
+#[allow(clippy::too_many_arguments)]
+fn end_match(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    knockout: Res<KnockOut>,
+    session: Res<Session<GgrsConfig>>,
+    peers: Option<Res<MatchPeers>>,
+    mut confirmations: ResMut<MatchOverConfirmations>,
+    mut inbox: ResMut<LobbyInbox>,
+    mut socket: Option<ResMut<MatchboxSocket<MultipleChannels>>>,
+    mut direct: Option<ResMut<DirectSocket>>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    // the other players can confirm it first, their word is kept until the local side catches up
+    inbox.0.retain(|(peer, message)| {
+        if !matches!(message, LobbyMessage::MatchOver) {
+            return true;
+        }
+        info!("peer {peer} confirmed the knockout");
+        if !confirmations.remote.contains(peer) {
+            confirmations.remote.push(*peer);
+        }
+        false
+    });
+
+    let Some(frame) = knockout.frame else {
+        return;
+    };
+    // training goes on however often the dummy falls, and replays end on their own
+    if matches!(*mode, GameMode::Training | GameMode::Replay) {
+        return;
+    }
+    if let Session::P2P(p2p) = session.as_ref() {
+        if p2p.confirmed_frame() < frame as i32 {
+            return;
+        }
+        if !confirmations.local {
+            info!("knockout on frame {frame} confirmed");
+            confirmations.local = true;
+            if let (Some(socket), Some(peers)) = (socket.as_mut(), &peers) {
+                for &player in peers.players.iter().filter(|p| **p != peers.local) {
+                    LobbyMessage::MatchOver.send(socket, player);
+                }
+            }
+            if let Some(direct) = direct.as_mut() {
+                direct.match_over = true;
+            }
+        }
+        let everyone_confirmed = match (&direct, &peers) {
+            (Some(direct), _) => direct.peer_match_over(),
+            (None, Some(peers)) => peers
+                .players
+                .iter()
+                .all(|p| *p == peers.local || confirmations.remote.contains(p)),
+            (None, None) => true,
+        };
+        if !everyone_confirmed {
+            return;
+        }
+    }
+    info!("knockout on frame {frame}, the match is over");
+    commands.remove_resource::<Session<GgrsConfig>>();
+    app_state.set(AppState::MatchOver);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn rematch_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mode: Res<GameMode>,
+    stage: Res<Stage>,
+    stages: Res<Stages>,
+    fighters: Query<(&Player, &Health)>,
+    mut cursor: ResMut<MenuCursor>,
+    mut direct: Option<ResMut<DirectSocket>>,
+) {
+    let entries = match *mode {
+        // spectators follow whatever the players pick
+        GameMode::Spectate => vec!["Quit to Menu"],
+        _ => vec!["Rematch", "Stage", "Character Select", "Quit to Menu"],
+    };
+    // the picks and votes still going around are for the match that just ended
+    if let Some(direct) = direct.as_mut() {
+        direct.next_round();
+    }
+    let standing: Vec<usize> = fighters
+        .iter()
+        .filter(|(_, health)| health.0 > 0)
+        .map(|(player, _)| player.handle)
+        .collect();
+    let title = match standing.as_slice() {
+        [winner] => format!("Player {} wins", winner + 1),
+        _ => "Double KO".to_string(),
+    };
+
+    cursor.0 = 0;
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        &title,
+        &entries,
+        OVERLAY_COLOR,
+        RematchUI,
+    );
+    commands.insert_resource(RematchMenu {
+        entries,
+        stage: stages.index_or_default(Some(stage.0.name.as_str())),
+        local_vote: None,
+        votes: Vec::new(),
+    });
+}
+
+fn receive_rematch_votes(
+    stages: Res<Stages>,
+    peers: Res<MatchPeers>,
+    mut menu: ResMut<RematchMenu>,
+    mut inbox: ResMut<LobbyInbox>,
+) {
+    inbox.0.retain(|(peer, message)| {
+        let LobbyMessage::Rematch { stage, reselect } = message else {
+            return true;
+        };
+        if !peers.players.contains(peer) {
+            warn!("rematch vote from {peer}, who isn't playing");
+            return false;
+        }
+        info!("peer {peer} voted for a rematch on {stage}");
+        let vote = RematchVote {
+            stage: stages.index_or_default(Some(stage.as_str())),
+            reselect: *reselect,
+        };
+        menu.votes.retain(|(voter, _)| voter != peer);
+        menu.votes.push((*peer, vote));
+        false
+    });
+}
+
+/// A/D change the stage on the stage entry, picking any entry but quit votes for the next match
+fn rematch_input(
+    keys: Res<Input<KeyCode>>,
+    stages: Res<Stages>,
+    mut menu: ResMut<RematchMenu>,
+    mut cursor: ResMut<MenuCursor>,
+    mut socket: Option<ResMut<MatchboxSocket<MultipleChannels>>>,
+    mut direct: Option<ResMut<DirectSocket>>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    let picked = navigate_menu(&keys, &mut cursor, menu.entries.len());
+    let entry = menu.entries[cursor.0];
+    // the stage can't change anymore once voted for
+    if entry == "Stage" && menu.local_vote.is_none() {
+        let count = stages.0.len();
+        if keys.any_just_pressed([KeyCode::A, KeyCode::Left]) {
+            menu.stage = (menu.stage + count - 1) % count;
+        }
+        if keys.any_just_pressed([KeyCode::D, KeyCode::Right]) {
+            menu.stage = (menu.stage + 1) % count;
+        }
+    }
+    if !picked {
+        return;
+    }
+
+    let reselect = match entry {
+        "Rematch" | "Stage" => false,
+        "Character Select" => true,
+        _ => {
+            app_state.set(AppState::MainMenu);
+            return;
+        }
+    };
+    if menu.local_vote.is_some() {
+        return;
+    }
+    menu.local_vote = Some(RematchVote {
+        stage: menu.stage,
+        reselect,
+    });
+    let stage = stages.0[menu.stage].name.clone();
+    if let Some(socket) = socket.as_mut() {
+        LobbyMessage::Rematch { stage, reselect }.broadcast(socket);
+    } else if let Some(direct) = direct.as_mut() {
+        // goes out with the next hellos
+        direct.rematch = Some((stage, reselect));
+    }
+}
+
+fn update_rematch_entries(
+    stages: Res<Stages>,
+    menu: Res<RematchMenu>,
+    peers: Option<Res<MatchPeers>>,
+    mut entries: Query<(&MenuEntry, &mut Text), Without<MenuMessage>>,
+    mut message: Query<&mut Text, With<MenuMessage>>,
+) {
+    for (entry, mut text) in &mut entries {
+        if menu.entries[entry.0] == "Stage" {
+            text.sections[0].value = format!("Stage: < {} >", stages.0[menu.stage].name);
+        }
+    }
+
+    let Ok(mut message) = message.get_single_mut() else {
+        return;
+    };
+    let spectating = peers
+        .as_ref()
+        .is_some_and(|peers| !peers.players.contains(&peers.local));
+    message.sections[0].value = if spectating {
+        "Waiting for the players to choose".to_string()
+    } else if menu.local_vote.is_some() && peers.is_some() {
+        "Waiting for the other player to choose".to_string()
+    } else if !menu.votes.is_empty() {
+        "The other player is ready for the next match".to_string()
+    } else {
+        String::new()
+    };
+}
+
+/// Starts the next match once every player voted, agreeing on the stage the way the lobby does
+fn start_rematch(
+    mut commands: Commands,
+    stages: Res<Stages>,
+    menu: Res<RematchMenu>,
+    peers: Option<Res<MatchPeers>>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    let mut votes: Vec<(Option<PeerId>, RematchVote)> = menu
+        .votes
+        .iter()
+        .map(|(peer, vote)| (Some(*peer), *vote))
+        .collect();
+    match &peers {
+        Some(peers) => {
+            if peers.players.contains(&peers.local) {
+                let Some(vote) = menu.local_vote else {
+                    return;
+                };
+                votes.push((Some(peers.local), vote));
+            }
+            let all_voted = peers
+                .players
+                .iter()
+                .all(|player| votes.iter().any(|(voter, _)| *voter == Some(*player)));
+            if !all_voted {
+                return;
+            }
+        }
+        None => match menu.local_vote {
+            Some(vote) => votes.push((None, vote)),
+            None => return,
+        },
+    }
+
+    votes.sort_by_key(|(voter, _)| *voter);
+    let sum: usize = votes.iter().map(|(_, vote)| vote.stage).sum();
+    let stage = votes[sum % votes.len()].1.stage;
+    let reselect = votes.iter().any(|(_, vote)| vote.reselect);
+    info!("next match on {}", stages.0[stage].name);
+
+    commands.insert_resource(Stage(stages.0[stage].clone()));
+    app_state.set(if reselect {
+        AppState::CharacterSelect
+    } else {
+        AppState::InGame
+    });
+}
@@ -0,0 +1,223 @@
+use crate::{
+    character::Characters,
+    character_select::{FighterSelection, Selections},
+    lobby::MatchSettings,
+    menu::GameMode,
+    settings::{Settings, FPS_RANGE},
+    stage::{Stage, Stages},
+    AppState, FrameCount, GgrsConfig,
+};
+use bevy::prelude::*;
+use bevy_ggrs::PlayerInputs;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Where the last finished match gets saved to
+const REPLAY_PATH: &str = "replays/last.ron";
+/// Frames a replay keeps going after its last input before going back to the menu
+const REPLAY_END_DELAY: usize = 120;
+
+/// Everything needed to play a match back: the simulation is deterministic,
+/// so the stage, the picks and every frame of input recreate it exactly
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Replay {
+    pub stage: String,
+    /// Character name and palette by player handle
+    pub fighters: Vec<(String, usize)>,
+    /// Buttons of both players for every frame
+    pub inputs: Vec<[u16; 2]>,
+    /// Frames per second the match was simulated at, the physics step depends on it
+    pub fps: usize,
+}
+
+impl Replay {
+    pub fn load() -> Result<Replay, String> {
+        Replay::load_from(Path::new(REPLAY_PATH))
+    }
+
+    fn load_from(path: &Path) -> Result<Replay, String> {
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        ron::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        self.save_to(Path::new(REPLAY_PATH))
+    }
+
+    fn save_to(&self, path: &Path) -> Result<(), String> {
+        let contents = ron::to_string(self).map_err(|err| err.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+
+    /// Looks up the replay's stage and picks, failing if the game data changed since it was saved
+    pub fn resolve(
+        &self,
+        stages: &Stages,
+        characters: &Characters,
+    ) -> Result<(Stage, Selections), String> {
+        if !FPS_RANGE.contains(&self.fps) {
+            return Err(format!("recorded at an unsupported {} fps", self.fps));
+        }
+        let stage = stages
+            .0
+            .iter()
+            .find(|stage| stage.name == self.stage)
+            .ok_or_else(|| format!("unknown stage {}", self.stage))?;
+        let mut selections = Selections::default();
+        for (handle, (name, palette)) in self.fighters.iter().enumerate().take(2) {
+            let character = characters
+                .find(name)
+                .ok_or_else(|| format!("unknown character {name}"))?;
+            selections.0[handle] = FighterSelection {
+                character,
+                palette: *palette,
+            };
+        }
+        Ok((Stage(stage.clone()), selections))
+    }
+
+    /// Buttons the player with `handle` pressed on `frame`, nothing once the replay ran out
+    pub fn buttons(&self, frame: usize, handle: usize) -> u16 {
+        self.inputs
+            .get(frame)
+            .and_then(|buttons| buttons.get(handle))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Match being recorded, kept outside of the rollback state
+#[derive(Resource, Default)]
+pub struct ReplayRecorder(pub Replay);
+
+/// Replay being watched, its inputs are fed in instead of the keyboard's
+#[derive(Resource)]
+pub struct ReplayPlayback(pub Replay);
+
+/// Starts a fresh recording for the match about to begin
+pub fn start_recording(
+    mut recorder: ResMut<ReplayRecorder>,
+    mode: Res<GameMode>,
+    settings: Res<Settings>,
+    match_settings: Option<Res<MatchSettings>>,
+    stage: Res<Stage>,
+    selections: Res<Selections>,
+    characters: Res<Characters>,
+) {
+    // online the match runs at the negotiated fps, which can be the host's rather than ours
+    let fps = match (&match_settings, mode.is_online()) {
+        (Some(match_settings), true) => match_settings.0.fps,
+        _ => settings.fps,
+    };
+    recorder.0 = Replay {
+        stage: stage.name.clone(),
+        fighters: selections
+            .0
+            .iter()
+            .map(|selection| {
+                let name = characters.get(&selection.character).name.clone();
+                (name, selection.palette)
+            })
+            .collect(),
+        inputs: Vec::new(),
+        fps,
+    };
+}
+
+/// Stores the inputs of the frame being simulated. Rollbacks simulate frames again
+/// with corrected inputs, which replace whatever was recorded from that frame on.
+pub fn record_replay(
+    frame_count: Res<FrameCount>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let frame = **frame_count;
+    let buttons = |handle: usize| inputs.get(handle).map_or(0, |input| input.0.buttons);
+    recorder.0.inputs.truncate(frame);
+    if recorder.0.inputs.len() == frame {
+        recorder.0.inputs.push([buttons(0), buttons(1)]);
+    }
+}
+
+/// Writes the recorded match to disk, called when leaving it
+pub fn save_replay(mut recorder: ResMut<ReplayRecorder>, playback: Option<Res<ReplayPlayback>>) {
+    // watching a replay records it again, no need to save that
+    if recorder.0.inputs.is_empty() || playback.is_some() {
+        return;
+    }
+    match recorder.0.save() {
+        Ok(()) => info!("saved replay to {REPLAY_PATH}"),
+        Err(err) => warn!("failed to save replay: {err}"),
+    }
+    recorder.0 = Replay::default();
+}
+
+/// Goes back to the menu once the replay is over
+pub fn end_replay(mut app_state: ResMut<NextState<AppState>>) {
+    info!("replay finished");
+    app_state.set(AppState::MainMenu);
+}
+
+/// Whether a replay being watched has played all the way through
+pub fn replay_finished(frame_count: Res<FrameCount>, playback: Res<ReplayPlayback>) -> bool {
+    **frame_count > playback.0.inputs.len() + REPLAY_END_DELAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_round_trips_through_a_file() {
+        let stages = Stages::default();
+        let characters = Characters::default();
+        let fighter = characters.0[0].name.clone();
+        let replay = Replay {
+            stage: stages.0[stages.0.len() - 1].name.clone(),
+            fighters: vec![(fighter.clone(), 0), (fighter.clone(), 1)],
+            inputs: vec![[0, 0], [1, 2], [4, 0]],
+            fps: 90,
+        };
+
+        let path = std::env::temp_dir().join(format!("replay-{}.ron", std::process::id()));
+        replay.save_to(&path).unwrap();
+        let loaded = Replay::load_from(&path);
+        fs::remove_file(&path).ok();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.inputs, replay.inputs);
+        assert_eq!(loaded.fps, replay.fps);
+
+        let (stage, selections) = loaded.resolve(&stages, &characters).unwrap();
+        assert_eq!(stage.name, replay.stage);
+        let character = characters.find(&fighter).unwrap();
+        assert_eq!(
+            selections.0[0],
+            FighterSelection {
+                character,
+                palette: 0
+            }
+        );
+        assert_eq!(
+            selections.0[1],
+            FighterSelection {
+                character,
+                palette: 1
+            }
+        );
+    }
+
+    #[test]
+    fn replay_at_an_unsupported_fps_is_refused() {
+        let stages = Stages::default();
+        let characters = Characters::default();
+        let replay = Replay {
+            stage: stages.0[0].name.clone(),
+            fps: 0,
+            ..default()
+        };
+        assert!(replay.resolve(&stages, &characters).is_err());
+    }
+}
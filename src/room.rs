@@ -0,0 +1,253 @@
+use crate::{
+    args::Args,
+    menu::{
+        despawn_menu, highlight_menu_entries, navigate_menu, spawn_menu, GameMode, MenuCursor,
+        MenuEntry, MenuMessage, MENU_BACKGROUND,
+    },
+    AppState,
+};
+use bevy::prelude::*;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+/// Characters room codes are made of, leaving out the ones easily mistaken for each other
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 6;
+
+const ROOM_ENTRIES: [&str; 5] = ["Create Room", "Code", "Join Room", "Spectate Room", "Back"];
+
+/// Matchbox room the lobby connects to
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Room {
+    /// Matched up with whoever else is looking for a game
+    #[default]
+    Public,
+    /// Given with `--room`
+    Named(String),
+    /// Created in game and shared by its code
+    Private(String),
+    /// Hosted on the local network, on the signaling server of the host at this address
+    Lan(SocketAddr),
+}
+
+impl Room {
+    /// The room on the command line, or public matchmaking without one
+    pub fn from_args(args: &Args) -> Room {
+        args.room.clone().map_or(Room::Public, Room::Named)
+    }
+
+    /// Where the matchbox socket connects to, `matchbox` being the server given with `--matchbox`
+    pub fn url(&self, matchbox: &str, players: usize) -> String {
+        match self {
+            Room::Public => format!("{matchbox}/bevy_ggrs?next={players}"),
+            Room::Named(name) => format!("{matchbox}/{name}"),
+            Room::Private(code) => format!("{matchbox}/bevy_fighter_{code}"),
+            // the host's server only has the one room
+            Room::Lan(addr) => format!("ws://{addr}/bevy_fighter_lan"),
+        }
+    }
+}
+
+/// A new random room code
+fn new_code() -> String {
+    Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(CODE_LEN)
+        .map(|byte| CODE_ALPHABET[*byte as usize % CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+/// Keeps the characters of `text` that can be in a code, so pasted or lowercase codes still work
+fn normalize_code(text: &str) -> String {
+    text.chars()
+        .map(|c| c.to_ascii_uppercase())
+        .filter(|c| c.is_ascii() && CODE_ALPHABET.contains(&(*c as u8)))
+        .take(CODE_LEN)
+        .collect()
+}
+
+/// How a code is shown, split in two halves to be easier to read out
+pub fn format_code(code: &str) -> String {
+    if code.len() <= CODE_LEN / 2 {
+        return code.to_string();
+    }
+    let (first, second) = code.split_at(CODE_LEN / 2);
+    format!("{first}-{second}")
+}
+
+/// The system clipboard, opened once and kept for as long as the game runs. On Linux the copied
+/// text is gone as soon as the clipboard that copied it is, unless a clipboard manager took it.
+pub struct Clipboard(Result<arboard::Clipboard, String>);
+
+impl Clipboard {
+    fn open() -> Self {
+        let clipboard = arboard::Clipboard::new().map_err(|err| err.to_string());
+        if let Err(err) = &clipboard {
+            warn!("no clipboard for room codes: {err}");
+        }
+        Clipboard(clipboard)
+    }
+
+    pub fn copy(&mut self, text: &str) -> Result<(), String> {
+        let clipboard = self.0.as_mut().map_err(|err| err.clone())?;
+        clipboard
+            .set_text(text.to_string())
+            .map_err(|err| err.to_string())
+    }
+
+    fn paste(&mut self) -> Result<String, String> {
+        let clipboard = self.0.as_mut().map_err(|err| err.clone())?;
+        clipboard.get_text().map_err(|err| err.to_string())
+    }
+}
+
+/// Whether Ctrl and `key` were pressed together this frame
+pub fn ctrl_pressed(keys: &Input<KeyCode>, key: KeyCode) -> bool {
+    keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) && keys.just_pressed(key)
+}
+
+/// The code being typed on the room screen
+#[derive(Resource, Default)]
+struct RoomScreen {
+    code: String,
+    editing: bool,
+    message: String,
+}
+
+/// Marker component for room screen UI
+#[derive(Component)]
+struct RoomUI;
+
+pub struct RoomPlugin;
+
+impl Plugin for RoomPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Room>()
+            .insert_non_send_resource(Clipboard::open())
+            .add_systems(OnEnter(AppState::RoomCode), room_startup)
+            .add_systems(
+                Update,
+                (room_input, update_room_entries, highlight_menu_entries)
+                    .chain()
+                    .run_if(in_state(AppState::RoomCode)),
+            )
+            .add_systems(OnExit(AppState::RoomCode), despawn_menu::<RoomUI>);
+    }
+}
+
+fn room_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut cursor: ResMut<MenuCursor>,
+) {
+    cursor.0 = 0;
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        "Private Room",
+        &ROOM_ENTRIES,
+        MENU_BACKGROUND,
+        RoomUI,
+    );
+    commands.insert_resource(RoomScreen {
+        message: "Create a room and send its code to a friend, or join theirs".to_string(),
+        ..default()
+    });
+}
+
+/// Typing a code works like the lobby's nickname, pick the code entry and Enter when done.
+/// Ctrl+V pastes a code at any time.
+#[allow(clippy::too_many_arguments)]
+fn room_input(
+    keys: Res<Input<KeyCode>>,
+    mut typed: EventReader<ReceivedCharacter>,
+    mut screen: ResMut<RoomScreen>,
+    mut cursor: ResMut<MenuCursor>,
+    mut room: ResMut<Room>,
+    mut mode: ResMut<GameMode>,
+    mut clipboard: NonSendMut<Clipboard>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    if ctrl_pressed(&keys, KeyCode::V) {
+        typed.clear();
+        match clipboard.paste() {
+            Ok(text) => {
+                screen.code = normalize_code(&text);
+                screen.editing = false;
+            }
+            Err(err) => screen.message = format!("Nothing to paste: {err}"),
+        }
+        return;
+    }
+    if screen.editing {
+        for typed in typed.iter() {
+            if screen.code.len() < CODE_LEN {
+                let code = format!("{}{}", screen.code, typed.char);
+                screen.code = normalize_code(&code);
+            }
+        }
+        if keys.just_pressed(KeyCode::Back) {
+            screen.code.pop();
+        }
+        if keys.any_just_pressed([KeyCode::Return, KeyCode::Escape]) {
+            screen.editing = false;
+        }
+        return;
+    }
+    // the keys pressed to get here shouldn't end up in the code later
+    typed.clear();
+
+    if keys.just_pressed(KeyCode::Escape) {
+        app_state.set(AppState::MainMenu);
+        return;
+    }
+    if !navigate_menu(&keys, &mut cursor, ROOM_ENTRIES.len()) {
+        return;
+    }
+
+    let next_mode = match ROOM_ENTRIES[cursor.0] {
+        "Create Room" => {
+            screen.code = new_code();
+            GameMode::Online
+        }
+        "Code" => {
+            screen.editing = true;
+            return;
+        }
+        "Join Room" => GameMode::Online,
+        "Spectate Room" => GameMode::Spectate,
+        _ => {
+            app_state.set(AppState::MainMenu);
+            return;
+        }
+    };
+    if screen.code.len() < CODE_LEN {
+        screen.message = format!("Room codes are {CODE_LEN} characters long");
+        return;
+    }
+    info!("joining private room {}", screen.code);
+    *room = Room::Private(screen.code.clone());
+    *mode = next_mode;
+    app_state.set(AppState::Lobby);
+}
+
+fn update_room_entries(
+    screen: Res<RoomScreen>,
+    mut entries: Query<(&MenuEntry, &mut Text), Without<MenuMessage>>,
+    mut message: Query<&mut Text, With<MenuMessage>>,
+) {
+    for (entry, mut text) in &mut entries {
+        if ROOM_ENTRIES[entry.0] == "Code" {
+            let cursor = if screen.editing { "_" } else { "" };
+            text.sections[0].value = format!("Code: {}{cursor}", format_code(&screen.code));
+        }
+    }
+    if let Ok(mut message) = message.get_single_mut() {
+        message.sections[0].value = if screen.editing {
+            "Type the code, Enter when done".to_string()
+        } else {
+            format!("{}\nCtrl+V pastes a code", screen.message)
+        };
+    }
+}
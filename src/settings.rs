@@ -0,0 +1,264 @@
+use crate::{
+    args::Args,
+    menu::{
+        despawn_menu, highlight_menu_entries, navigate_menu, spawn_menu, MenuCursor, MenuEntry,
+        MENU_BACKGROUND,
+    },
+    AppState,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+pub const INPUT_DELAY_RANGE: RangeInclusive<usize> = 0..=8;
+/// A synctest session needs the prediction window wider than its check distance
+pub const MAX_PREDICTION_RANGE: RangeInclusive<usize> = 4..=16;
+pub const FPS_RANGE: RangeInclusive<usize> = 30..=120;
+/// 0 turns desync detection off
+pub const DESYNC_INTERVAL_RANGE: RangeInclusive<u32> = 0..=60;
+pub const DISCONNECT_TIMEOUT_RANGE: RangeInclusive<u64> = 500..=10000;
+pub const DISCONNECT_NOTIFY_RANGE: RangeInclusive<u64> = 100..=5000;
+/// The host only keeps 60 frames of inputs around for spectators, with some slack for catching up
+pub const SPECTATOR_DELAY_RANGE: RangeInclusive<usize> = 0..=45;
+
+const SETTINGS_ENTRIES: [&str; 8] = [
+    "Input delay",
+    "Max prediction",
+    "FPS",
+    "Desync check interval",
+    "Disconnect timeout",
+    "Disconnect notify delay",
+    "Spectator delay",
+    "Back",
+];
+
+/// Session settings, starting out as given on the command line and changed on the settings screen.
+/// Online the ones every peer has to agree on get negotiated in the lobby.
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// Frames local inputs are held back, trading responsiveness for fewer rollbacks
+    pub input_delay: usize,
+    /// Frames the session can run ahead of the remote inputs before waiting for them
+    pub max_prediction: usize,
+    /// Frames simulated per second
+    pub fps: usize,
+    /// Frames between checksum comparisons with the other players, 0 turns them off
+    pub desync_interval: u32,
+    /// Milliseconds without hearing from a player before they get disconnected
+    pub disconnect_timeout: u64,
+    /// Milliseconds without hearing from a player before the connection counts as interrupted
+    pub disconnect_notify_delay: u64,
+    /// Frames a spectated match plays behind the players
+    pub spectator_delay: usize,
+}
+
+impl Settings {
+    pub fn from_args(args: &Args) -> Result<Settings, String> {
+        let settings = Settings {
+            input_delay: args.input_delay,
+            max_prediction: args.max_prediction,
+            fps: args.fps,
+            desync_interval: args.desync_interval,
+            disconnect_timeout: args.disconnect_timeout,
+            disconnect_notify_delay: args.disconnect_notify_delay,
+            spectator_delay: args.spectator_delay,
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        fn check<T: PartialOrd + std::fmt::Display>(
+            name: &str,
+            value: T,
+            range: RangeInclusive<T>,
+        ) -> Result<(), String> {
+            if range.contains(&value) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{name} is {value}, it has to be between {} and {}",
+                    range.start(),
+                    range.end()
+                ))
+            }
+        }
+        check("input delay", self.input_delay, INPUT_DELAY_RANGE)?;
+        check("max prediction", self.max_prediction, MAX_PREDICTION_RANGE)?;
+        check("fps", self.fps, FPS_RANGE)?;
+        check(
+            "desync interval",
+            self.desync_interval,
+            DESYNC_INTERVAL_RANGE,
+        )?;
+        check(
+            "disconnect timeout",
+            self.disconnect_timeout,
+            DISCONNECT_TIMEOUT_RANGE,
+        )?;
+        check(
+            "disconnect notify delay",
+            self.disconnect_notify_delay,
+            DISCONNECT_NOTIFY_RANGE,
+        )?;
+        check(
+            "spectator delay",
+            self.spectator_delay,
+            SPECTATOR_DELAY_RANGE,
+        )?;
+        if self.disconnect_notify_delay >= self.disconnect_timeout {
+            return Err("disconnect notify delay has to be shorter than the timeout".to_string());
+        }
+        Ok(())
+    }
+
+    /// Settings to play with `host`, the peer with the lowest id. Whatever changes the simulation
+    /// has to be the same for everyone, while delays and timeouts only affect the local side.
+    pub fn negotiated(&self, host: &Settings) -> Settings {
+        Settings {
+            max_prediction: host.max_prediction,
+            fps: host.fps,
+            desync_interval: host.desync_interval,
+            ..*self
+        }
+    }
+}
+
+/// Marker component for settings UI
+#[derive(Component)]
+struct SettingsUI;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(AppState::Settings), settings_startup)
+            .add_systems(
+                Update,
+                (
+                    settings_input,
+                    update_settings_entries,
+                    highlight_menu_entries,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Settings)),
+            )
+            .add_systems(OnExit(AppState::Settings), despawn_menu::<SettingsUI>);
+    }
+}
+
+fn settings_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut cursor: ResMut<MenuCursor>,
+) {
+    cursor.0 = 0;
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        "Settings",
+        &SETTINGS_ENTRIES,
+        MENU_BACKGROUND,
+        SettingsUI,
+    );
+}
+
+/// A/D change the highlighted setting, Escape or I go back to the main menu
+fn settings_input(
+    keys: Res<Input<KeyCode>>,
+    mut cursor: ResMut<MenuCursor>,
+    mut settings: ResMut<Settings>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    let picked = navigate_menu(&keys, &mut cursor, SETTINGS_ENTRIES.len());
+    let back = keys.any_just_pressed([KeyCode::Escape, KeyCode::I]);
+    if back || (picked && SETTINGS_ENTRIES[cursor.0] == "Back") {
+        app_state.set(AppState::MainMenu);
+        return;
+    }
+
+    let increase = if keys.any_just_pressed([KeyCode::A, KeyCode::Left]) {
+        false
+    } else if keys.any_just_pressed([KeyCode::D, KeyCode::Right]) {
+        true
+    } else {
+        return;
+    };
+
+    let mut changed = *settings;
+    match SETTINGS_ENTRIES[cursor.0] {
+        "Input delay" => {
+            changed.input_delay = step(changed.input_delay, 1, increase, INPUT_DELAY_RANGE)
+        }
+        "Max prediction" => {
+            changed.max_prediction = step(changed.max_prediction, 1, increase, MAX_PREDICTION_RANGE)
+        }
+        "FPS" => changed.fps = step(changed.fps, 10, increase, FPS_RANGE),
+        "Desync check interval" => {
+            changed.desync_interval =
+                step(changed.desync_interval, 1, increase, DESYNC_INTERVAL_RANGE)
+        }
+        "Disconnect timeout" => {
+            changed.disconnect_timeout = step(
+                changed.disconnect_timeout,
+                500,
+                increase,
+                DISCONNECT_TIMEOUT_RANGE,
+            )
+        }
+        "Disconnect notify delay" => {
+            changed.disconnect_notify_delay = step(
+                changed.disconnect_notify_delay,
+                100,
+                increase,
+                DISCONNECT_NOTIFY_RANGE,
+            )
+        }
+        "Spectator delay" => {
+            changed.spectator_delay =
+                step(changed.spectator_delay, 5, increase, SPECTATOR_DELAY_RANGE)
+        }
+        _ => {}
+    }
+    // changes that would make the settings invalid, like a notify delay past the timeout, are ignored
+    if changed.validate().is_ok() {
+        *settings = changed;
+    }
+}
+
+/// Moves `value` up or down by `by`, staying inside `range`
+fn step<T>(value: T, by: T, increase: bool, range: RangeInclusive<T>) -> T
+where
+    T: Copy + Ord + std::ops::Add<Output = T> + std::ops::Sub<Output = T>,
+{
+    let (min, max) = range.into_inner();
+    let value = if increase {
+        value + by
+    } else if value >= min + by {
+        value - by
+    } else {
+        min
+    };
+    value.clamp(min, max)
+}
+
+fn update_settings_entries(settings: Res<Settings>, mut entries: Query<(&MenuEntry, &mut Text)>) {
+    for (entry, mut text) in &mut entries {
+        let name = SETTINGS_ENTRIES[entry.0];
+        text.sections[0].value = match name {
+            "Input delay" => format!("{name}: < {} frames >", settings.input_delay),
+            "Max prediction" => format!("{name}: < {} frames >", settings.max_prediction),
+            "FPS" => format!("{name}: < {} >", settings.fps),
+            "Desync check interval" if settings.desync_interval == 0 => format!("{name}: < off >"),
+            "Desync check interval" => {
+                format!("{name}: < every {} frames >", settings.desync_interval)
+            }
+            "Disconnect timeout" => format!("{name}: < {}ms >", settings.disconnect_timeout),
+            "Disconnect notify delay" => {
+                format!("{name}: < {}ms >", settings.disconnect_notify_delay)
+            }
+            "Spectator delay" => format!("{name}: < {} frames >", settings.spectator_delay),
+            _ => name.to_string(),
+        };
+    }
+}
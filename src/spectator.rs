@@ -0,0 +1,83 @@
+use crate::{
+    args::Args,
+    configure_session, in_match,
+    lobby::{GgrsChannel, MatchPeers, MatchSettings},
+    settings::Settings,
+    GgrsConfig,
+};
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs::SpectatorSession, Session};
+use bevy_matchbox::prelude::*;
+
+/// Frames a spectator may fall behind on top of their delay before catching up.
+/// The host's inputs only stay buffered for 60 frames, so delay and slack have to stay below that.
+const CATCHUP_SLACK: usize = 10;
+/// Frames simulated per frame while catching up
+const CATCHUP_SPEED: usize = 2;
+
+/// Spectator session held back at the start of the match, so the confirmed inputs pile up and
+/// the match plays behind the players by the spectator delay
+#[derive(Resource)]
+pub struct DelayedSpectatorSession {
+    session: SpectatorSession<GgrsConfig>,
+    /// Seconds since startup at which the session starts playing
+    release_at: f32,
+}
+
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            release_spectator_session
+                .run_if(in_match.and_then(resource_exists::<DelayedSpectatorSession>())),
+        );
+    }
+}
+
+pub fn start_spectator_session(
+    mut commands: Commands,
+    time: Res<Time>,
+    args: Res<Args>,
+    settings: Res<MatchSettings>,
+    peers: Res<MatchPeers>,
+    shared_channel: Option<Res<GgrsChannel>>,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+) {
+    let Settings {
+        spectator_delay,
+        fps,
+        ..
+    } = settings.0;
+    let channel = GgrsChannel::get(&mut commands, &mut socket, shared_channel.as_deref());
+    let session = configure_session(&mut commands, args.players, &settings.0)
+        .with_max_frames_behind(spectator_delay + CATCHUP_SLACK)
+        .expect("invalid max frames behind")
+        .with_catchup_speed(CATCHUP_SPEED)
+        .expect("invalid catchup speed")
+        .start_spectator_session(peers.host(), channel);
+
+    info!(
+        "spectating {}, {spectator_delay} frames behind",
+        peers.host()
+    );
+    commands.insert_resource(DelayedSpectatorSession {
+        session,
+        release_at: time.elapsed_seconds() + spectator_delay as f32 / fps as f32,
+    });
+}
+
+fn release_spectator_session(world: &mut World) {
+    let now = world.resource::<Time>().elapsed_seconds();
+    let mut delayed = world.resource_mut::<DelayedSpectatorSession>();
+    // keeps the connection to the host alive and collects their inputs in the meantime
+    delayed.session.poll_remote_clients();
+    if now < delayed.release_at {
+        return;
+    }
+    if let Some(delayed) = world.remove_resource::<DelayedSpectatorSession>() {
+        info!("starting to play back the match");
+        world.insert_resource(Session::Spectator(delayed.session));
+    }
+}
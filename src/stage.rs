@@ -0,0 +1,208 @@
+use crate::{in_match, MainCamera};
+use bevy::{prelude::*, transform::TransformSystem};
+use bevy_xpbd_2d::math::*;
+use serde::Deserialize;
+
+/// Stage files are baked into the binary for the same reason character files are,
+/// every peer has to simulate the exact same floor and walls.
+pub const STAGE_FILES: &[(&str, &str)] = &[
+    ("dojo", include_str!("../assets/stages/dojo.ron")),
+    ("duskDojo", include_str!("../assets/stages/duskDojo.ron")),
+];
+
+/// Half the height of a fighter's body, how far its center sits above the floor
+pub const FIGHTER_HALF_HEIGHT: f32 = 25.0;
+/// Thickness of the floor, ceiling and wall blocks
+pub const WALL_THICKNESS: f32 = 50.0;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BackgroundLayer {
+    pub image: String,
+    #[serde(default)]
+    pub offset: Vec2,
+    pub scale: Vec2,
+    /// Drawing order, lower is further back
+    #[serde(default)]
+    pub z: f32,
+    /// Color the image gets multiplied with
+    #[serde(default = "BackgroundLayer::default_tint")]
+    pub tint: (f32, f32, f32),
+    /// How much the layer moves along with the camera, 0 stays put on the stage
+    /// and 1 sticks to the screen, so far away layers are close to 1
+    #[serde(default)]
+    pub parallax: f32,
+    /// Horizontal speed the layer drifts at on its own, wrapping around the stage
+    #[serde(default)]
+    pub drift: f32,
+    /// Plays the image as a sprite sheet instead of a still
+    #[serde(default)]
+    pub animation: Option<LayerAnimation>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct LayerAnimation {
+    pub tile_size: Vec2,
+    pub columns: usize,
+    pub rows: usize,
+    pub fps: f32,
+}
+
+impl BackgroundLayer {
+    fn default_tint() -> (f32, f32, f32) {
+        (1.0, 1.0, 1.0)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StageData {
+    pub name: String,
+    pub layers: Vec<BackgroundLayer>,
+    /// Height of the top of the floor
+    pub floor: f32,
+    /// Height of the bottom of the ceiling
+    pub ceiling: f32,
+    /// Distance from the middle of the stage to the inside of either wall
+    pub walls: f32,
+    /// Where each player starts along the floor, by player handle
+    pub spawn_points: Vec<f32>,
+    /// Looped while fighting on the stage
+    #[serde(default)]
+    pub music: Option<String>,
+}
+
+impl StageData {
+    /// Height of a fighter's center when standing on the floor
+    pub fn ground_y(&self) -> f32 {
+        self.floor + FIGHTER_HALF_HEIGHT
+    }
+
+    pub fn spawn_point(&self, handle: usize) -> Vector {
+        Vector::new(self.spawn_points[handle], self.ground_y())
+    }
+
+    /// Everything inside the outer edges of the walls, floor and ceiling
+    pub fn bounds(&self) -> Rect {
+        Rect::new(
+            -self.walls - WALL_THICKNESS,
+            self.floor - WALL_THICKNESS,
+            self.walls + WALL_THICKNESS,
+            self.ceiling + WALL_THICKNESS,
+        )
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.spawn_points.len() < 2 {
+            return Err("needs a spawn point for each player".to_string());
+        }
+        if let Some(x) = self.spawn_points.iter().find(|x| x.abs() >= self.walls) {
+            return Err(format!("spawn point {x} is outside the walls"));
+        }
+        if self.ceiling <= self.floor {
+            return Err("ceiling is below the floor".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Every stage that can be picked, in the order they show up in stage select
+#[derive(Resource, Debug)]
+pub struct Stages(pub Vec<StageData>);
+
+impl Default for Stages {
+    fn default() -> Self {
+        let stages = STAGE_FILES
+            .iter()
+            .map(|(file, contents)| {
+                let stage: StageData = ron::from_str(contents)
+                    .unwrap_or_else(|err| panic!("failed to parse stage {file}: {err}"));
+                if let Err(err) = stage.validate() {
+                    panic!("invalid stage {file}: {err}");
+                }
+                stage
+            })
+            .collect();
+        Stages(stages)
+    }
+}
+
+impl Stages {
+    /// Index of the stage called `name`, the first stage when there's no name or it's unknown
+    pub fn index_or_default(&self, name: Option<&str>) -> usize {
+        let Some(name) = name else {
+            return 0;
+        };
+        self.0
+            .iter()
+            .position(|stage| stage.name.eq_ignore_ascii_case(name))
+            .unwrap_or_else(|| {
+                warn!("unknown stage {name:?}, picking {}", self.0[0].name);
+                0
+            })
+    }
+}
+
+/// The stage the match is played on, agreed on by every peer before the session starts
+#[derive(Resource, Clone, Debug, Deref)]
+pub struct Stage(pub StageData);
+
+/// Background layer placed relative to the camera every frame.
+/// Backgrounds are purely cosmetic so none of this is part of the rollback state.
+#[derive(Component)]
+pub struct ParallaxLayer {
+    pub origin: Vec3,
+    pub parallax: f32,
+    pub drift: f32,
+    /// Width the drift wraps around at
+    pub wrap: f32,
+}
+
+/// Looping music of the stage, despawned along with the rest of the stage
+#[derive(Component)]
+pub struct StageMusic;
+
+/// Sprite sheet animation of a background layer, played on wall clock time
+#[derive(Component)]
+pub struct AnimatedLayer {
+    pub frames: usize,
+    pub fps: f32,
+}
+
+pub struct StagePlugin;
+
+impl Plugin for StagePlugin {
+    fn build(&self, app: &mut App) {
+        // after the camera moved in Update, before transforms are propagated for rendering
+        app.add_systems(
+            PostUpdate,
+            (update_parallax, animate_layers)
+                .before(TransformSystem::TransformPropagate)
+                .run_if(in_match),
+        );
+    }
+}
+
+fn update_parallax(
+    time: Res<Time>,
+    camera: Query<&Transform, With<MainCamera>>,
+    mut layers: Query<(&ParallaxLayer, &mut Transform), Without<MainCamera>>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let camera = camera.translation.truncate();
+    for (layer, mut transform) in &mut layers {
+        let mut offset = camera * layer.parallax;
+        if layer.drift != 0.0 && layer.wrap > 0.0 {
+            let half_wrap = layer.wrap / 2.0;
+            offset.x += (layer.drift * time.elapsed_seconds() + half_wrap).rem_euclid(layer.wrap)
+                - half_wrap;
+        }
+        transform.translation = layer.origin + offset.extend(0.0);
+    }
+}
+
+fn animate_layers(time: Res<Time>, mut layers: Query<(&AnimatedLayer, &mut TextureAtlasSprite)>) {
+    for (animation, mut sprite) in &mut layers {
+        sprite.index = (time.elapsed_seconds() * animation.fps) as usize % animation.frames;
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    combat::{AttackState, FighterState, Health, Hitstop, Invulnerable, ThrowInvuln},
+    input::throw_pressed,
+    meter::SuperMeter,
+    GgrsConfig, Player,
+};
+use bevy::prelude::*;
+use bevy_ggrs::PlayerInputs;
+use bevy_xpbd_2d::{math::*, prelude::*};
+use std::f32::consts::PI;
+
+/// Furthest apart the two fighters can be horizontally for a throw to connect
+pub const THROW_RANGE: f32 = 55.0;
+pub const THROW_VERTICAL_RANGE: f32 = 20.0;
+/// Frames at the start of a throw where the defender can break it by throwing too
+pub const THROW_TECH_WINDOW: u32 = 10;
+/// Total length of the throw, including the tech window
+pub const THROW_DURATION: u32 = 40;
+pub const THROW_DAMAGE: u32 = 120;
+pub const THROW_KNOCKDOWN: u32 = 45;
+pub const THROW_TECH_FRAMES: u32 = 16;
+/// Speed both fighters get pushed apart with when a throw is teched
+pub const THROW_TECH_PUSHBACK: f32 = 150.0;
+
+/// Where the victim is held relative to the thrower during the throw animation
+const THROW_HOLD_DISTANCE: f32 = 40.0;
+const THROW_CARRY_DISTANCE: f32 = 60.0;
+const THROW_ARC_HEIGHT: f32 = 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ThrowOutcome {
+    Teched,
+    Finished,
+}
+
+/// Checks for throw inputs and grabs any throwable fighter in range
+pub fn start_throws(
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut fighters: Query<(
+        &Player,
+        &Position,
+        &Hitstop,
+        &ThrowInvuln,
+        &Invulnerable,
+        &mut FighterState,
+        &AttackState,
+        &mut TextureAtlasSprite,
+    )>,
+) {
+    let targets: Vec<(usize, Vector, bool)> = fighters
+        .iter()
+        .map(
+            |(player, position, _, throw_invuln, invulnerable, state, ..)| {
+                let throwable = *state == FighterState::Idle
+                    && throw_invuln.frames == 0
+                    && !invulnerable.active();
+                (player.handle, position.0, throwable)
+            },
+        )
+        .collect();
+
+    // (thrower, victim, victim is to the right)
+    let mut grabs = Vec::new();
+    for (player, position, hitstop, _, _, state, attack, _) in &fighters {
+        // throws can't come out of an attack that's already playing
+        if *state != FighterState::Idle || hitstop.active() || attack.attacking() {
+            continue;
+        }
+        let buttons = inputs.get(player.handle).map_or(0, |input| input.0.buttons);
+        if !throw_pressed(buttons) {
+            continue;
+        }
+
+        let victim = targets.iter().find(|(handle, victim_position, throwable)| {
+            *handle != player.handle
+                && *throwable
+                && (victim_position.x - position.0.x).abs() <= THROW_RANGE
+                && (victim_position.y - position.0.y).abs() <= THROW_VERTICAL_RANGE
+        });
+        if let Some((victim, victim_position, _)) = victim {
+            grabs.push((player.handle, *victim, victim_position.x > position.0.x));
+        }
+    }
+
+    for (player, _, _, _, _, mut state, _, mut sprite) in &mut fighters {
+        if let Some(&(_, victim, victim_to_right)) =
+            grabs.iter().find(|(t, ..)| *t == player.handle)
+        {
+            // grabbing each other on the same frame counts as a tech
+            if grabs
+                .iter()
+                .any(|(t, v, _)| *t == victim && *v == player.handle)
+            {
+                *state = FighterState::ThrowTeched {
+                    frames: THROW_TECH_FRAMES,
+                };
+                continue;
+            }
+            *state = FighterState::Throwing { frame: 0, victim };
+            sprite.flip_x = !victim_to_right;
+            sprite.index = 1;
+        } else if let Some(&(thrower, ..)) = grabs.iter().find(|(_, v, _)| *v == player.handle) {
+            *state = FighterState::Thrown { frame: 0, thrower };
+            sprite.index = 0;
+        }
+    }
+}
+
+/// Plays out throws in progress, carrying the victim along a fixed arc so both sides agree on it
+pub fn update_throws(
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut meter: ResMut<SuperMeter>,
+    mut fighters: Query<(
+        &Player,
+        &mut FighterState,
+        &mut Position,
+        &mut LinearVelocity,
+        &mut Health,
+        &mut TextureAtlasSprite,
+    )>,
+) {
+    let throwers: Vec<(usize, Vector, bool)> = fighters
+        .iter()
+        .filter(|(_, state, ..)| matches!(**state, FighterState::Throwing { .. }))
+        .map(|(player, _, position, _, _, sprite)| (player.handle, position.0, sprite.flip_x))
+        .collect();
+
+    let mut outcomes = Vec::new();
+    for (player, mut state, mut position, mut velocity, mut health, _) in &mut fighters {
+        let FighterState::Thrown { frame, thrower } = *state else {
+            continue;
+        };
+        let Some(&(_, thrower_position, facing_left)) =
+            throwers.iter().find(|(handle, ..)| *handle == thrower)
+        else {
+            *state = FighterState::Idle;
+            continue;
+        };
+        let facing = if facing_left { -1.0 } else { 1.0 };
+
+        let buttons = inputs.get(player.handle).map_or(0, |input| input.0.buttons);
+        if frame < THROW_TECH_WINDOW && throw_pressed(buttons) {
+            *state = FighterState::ThrowTeched {
+                frames: THROW_TECH_FRAMES,
+            };
+            velocity.0 = Vector::new(THROW_TECH_PUSHBACK * facing, 0.0);
+            outcomes.push((thrower, ThrowOutcome::Teched));
+            continue;
+        }
+
+        let frame = frame + 1;
+        let t = frame as f32 / THROW_DURATION as f32;
+        position.0 = thrower_position
+            + Vector::new(
+                facing * (THROW_HOLD_DISTANCE + THROW_CARRY_DISTANCE * t),
+                THROW_ARC_HEIGHT * (t * PI).sin(),
+            );
+        velocity.0 = Vector::ZERO;
+
+        if frame >= THROW_DURATION {
+            health.0 = health.0.saturating_sub(THROW_DAMAGE);
+            info!("throw landed, {} health left", health.0);
+            meter.on_hit(thrower, player.handle, THROW_DAMAGE, false);
+            *state = FighterState::Knockdown {
+                frames: THROW_KNOCKDOWN,
+                chosen: false,
+            };
+            outcomes.push((thrower, ThrowOutcome::Finished));
+        } else {
+            *state = FighterState::Thrown { frame, thrower };
+        }
+    }
+
+    for (player, mut state, _, mut velocity, _, mut sprite) in &mut fighters {
+        let FighterState::Throwing { frame, victim } = *state else {
+            continue;
+        };
+        velocity.0.x = 0.0;
+
+        let outcome = outcomes
+            .iter()
+            .find(|(thrower, _)| *thrower == player.handle)
+            .map(|(_, outcome)| *outcome);
+        match outcome {
+            Some(ThrowOutcome::Teched) => {
+                let facing = if sprite.flip_x { -1.0 } else { 1.0 };
+                velocity.0.x = -THROW_TECH_PUSHBACK * facing;
+                sprite.index = 0;
+                *state = FighterState::ThrowTeched {
+                    frames: THROW_TECH_FRAMES,
+                };
+            }
+            Some(ThrowOutcome::Finished) => {
+                sprite.index = 0;
+                *state = FighterState::Idle;
+            }
+            // the victim got out some other way, so let go
+            None if frame >= THROW_DURATION => {
+                sprite.index = 0;
+                *state = FighterState::Idle;
+            }
+            None => {
+                sprite.index = if frame < THROW_TECH_WINDOW { 1 } else { 4 };
+                *state = FighterState::Throwing {
+                    frame: frame + 1,
+                    victim,
+                };
+            }
+        }
+    }
+}